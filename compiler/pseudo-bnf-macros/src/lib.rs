@@ -0,0 +1,82 @@
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use pseudo_bnf_parser::{generate_rust, parse_grammar, validate, GrammarError, Options};
+use std::env;
+use std::fs;
+use std::path::Path;
+use syn::parse::{Parse, ParseStream};
+use syn::{parse_macro_input, Ident, LitStr, Token};
+
+enum GrammarSource {
+    Inline(LitStr),
+    File(LitStr),
+}
+
+impl Parse for GrammarSource {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        if input.peek(Ident) {
+            let keyword: Ident = input.parse()?;
+            if keyword != "file" {
+                return Err(syn::Error::new(keyword.span(), "expected `file = \"...\"`"));
+            }
+            input.parse::<Token![=]>()?;
+            return Ok(GrammarSource::File(input.parse()?));
+        }
+        Ok(GrammarSource::Inline(input.parse()?))
+    }
+}
+
+fn compile_errors(literal: &LitStr, origin: &str, errors: Vec<GrammarError>) -> TokenStream {
+    let mut tokens = proc_macro2::TokenStream::new();
+    for error in errors {
+        let message = format!("{}{}", origin, error);
+        tokens.extend(syn::Error::new(literal.span(), message).to_compile_error());
+    }
+    tokens.into()
+}
+
+// Expands to the types, `parse_*` functions and `parse` entry point generated
+// from a pseudo-BNF grammar, given either inline or as a file relative to the
+// crate root: `grammar!("...")` or `grammar!(file = "grammar.pseudo-bnf")`.
+// The expansion brings its own `use` items, so invoke it in a module of its
+// own.
+#[proc_macro]
+pub fn grammar(input: TokenStream) -> TokenStream {
+    let mut dependency = None;
+    let (literal, origin, source) = match parse_macro_input!(input as GrammarSource) {
+        GrammarSource::Inline(literal) => {
+            let source = literal.value();
+            (literal, String::new(), source)
+        }
+        GrammarSource::File(literal) => {
+            let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap_or_default();
+            let path = Path::new(&manifest_dir).join(literal.value());
+            match fs::read_to_string(&path) {
+                Ok(source) => {
+                    dependency = Some(path.display().to_string());
+                    (literal, format!("{}: ", path.display()), source)
+                }
+                Err(error) => {
+                    let message = format!("could not read {}: {}", path.display(), error);
+                    return syn::Error::new(literal.span(), message)
+                        .to_compile_error()
+                        .into();
+                }
+            }
+        }
+    };
+    let grammar = match parse_grammar(&source) {
+        Ok(grammar) => grammar,
+        Err(error) => return compile_errors(&literal, &origin, vec![error]),
+    };
+    if let Err(errors) = validate(&grammar, &Options::default()) {
+        return compile_errors(&literal, &origin, errors);
+    }
+    let mut rust = generate_rust(&grammar, &Options::default());
+    if let Some(path) = dependency {
+        // Makes cargo rebuild when the grammar file changes.
+        rust.push_str(&format!("const _: &str = include_str!({:?});\n", path));
+    }
+    rust.parse().expect("Generated parser is not valid Rust")
+}
@@ -0,0 +1,61 @@
+// These grammars only compile if the generator boxes the recursive references.
+
+#[allow(dead_code)]
+mod expressions {
+    pseudo_bnf_macros::grammar!(
+        r#"
+        <<IDENT>> ~= /[a-z][a-z-]*/
+        <NAME> ::= <<IDENT>>
+        <NUMBER> ::= <<INTEGER>>
+        <PARENTHESIZED> ::= "(" inner:<EXPRESSION> ")"
+        <NEGATED> ::= "-" operand:<EXPRESSION>
+        <EXPRESSION> ::= <NUMBER> | <NAME> | <PARENTHESIZED> | <NEGATED>
+        <<START>> ::= expressions:<EXPRESSION>*";"!
+    "#
+    );
+
+    fn depth(expression: &Expression) -> usize {
+        match expression {
+            Expression::Number(_) | Expression::Name(_) => 0,
+            Expression::Parenthesized(p) => 1 + depth(&p.inner),
+            Expression::Negated(n) => 1 + depth(&n.operand),
+        }
+    }
+
+    #[test]
+    fn parses_nested_expressions() {
+        let start = parse("-(-(x)); 3;").unwrap();
+        let depths: Vec<usize> = start.expressions.iter().map(depth).collect();
+        assert_eq!(depths, vec![4, 0]);
+    }
+}
+
+#[allow(dead_code)]
+mod cells {
+    pseudo_bnf_macros::grammar!(
+        r#"
+        <<IDENT>> ~= /[a-z][a-z-]*/
+        <NAME> ::= <<IDENT>>
+        <CELL> ::= name:<NAME> nested:( "{" <CELL> "}" )?
+        <<START>> ::= cells:<CELL>*
+    "#
+    );
+
+    fn names(cell: &Cell) -> Vec<&str> {
+        let mut names = vec![cell.name.as_str()];
+        if let Some(nested) = &cell.nested {
+            names.extend(self::names(&nested.cell));
+        }
+        names
+    }
+
+    #[test]
+    fn parses_nested_cells() {
+        let start = parse("cell { imp { damage { min } } } icon").unwrap();
+        let names: Vec<Vec<&str>> = start.cells.iter().map(names).collect();
+        assert_eq!(
+            names,
+            vec![vec!["cell", "imp", "damage", "min"], vec!["icon"]]
+        );
+    }
+}
@@ -0,0 +1,95 @@
+// Exercises the generated Visitor, VisitorMut and Fold traits through boxed,
+// optional and repeated fields and through choices.
+
+#[allow(dead_code)]
+mod statements {
+    pseudo_bnf_macros::grammar!(
+        r#"
+        <<IDENT>> ~= /[a-z][a-z-]*/
+        <NAME> ::= <<IDENT>>
+        <NUMBER> ::= <<INTEGER>>
+        <PARENTHESIZED> ::= "(" inner:<EXPRESSION> ")"
+        <NEGATED> ::= "-" operand:<EXPRESSION>
+        <EXPRESSION> ::= <NUMBER> | <NAME> | <PARENTHESIZED> | <NEGATED>
+        <STATEMENT> ::= value:<EXPRESSION> unit:<UNIT>?
+        <UNIT> ::= "in" <NAME>
+        <<START>> ::= statements:<STATEMENT>*";"!
+    "#
+    );
+
+    #[derive(Default)]
+    struct Leaves {
+        names: Vec<String>,
+        total: i64,
+    }
+
+    impl<'ast> Visitor<'ast> for Leaves {
+        fn visit_name(&mut self, node: &'ast Name) {
+            self.names.push(node.clone());
+        }
+
+        fn visit_number(&mut self, node: &'ast Number) {
+            self.total += node;
+        }
+    }
+
+    fn leaves(start: &Start) -> Leaves {
+        let mut leaves = Leaves::default();
+        leaves.visit_start(start);
+        leaves
+    }
+
+    struct Uppercase;
+
+    impl VisitorMut for Uppercase {
+        fn visit_name_mut(&mut self, node: &mut Name) {
+            *node = node.to_uppercase();
+        }
+
+        // Leaves the names of units alone.
+        fn visit_unit_mut(&mut self, _node: &mut Unit) {}
+    }
+
+    // Folds negated numbers into negative numbers, innermost first.
+    struct NegateNumbers;
+
+    impl Fold for NegateNumbers {
+        fn fold_expression(&mut self, node: Expression) -> Expression {
+            match fold_expression(self, node) {
+                Expression::Negated(negated) => match *negated.operand {
+                    Expression::Number(number) => Expression::Number(-number),
+                    operand => Expression::Negated(Box::new(Negated {
+                        operand: Box::new(operand),
+                        span: negated.span,
+                    })),
+                },
+                expression => expression,
+            }
+        }
+    }
+
+    #[test]
+    fn visitor_reaches_every_leaf() {
+        let start = parse("(x); -(2) in kg; 3;").unwrap();
+        let leaves = leaves(&start);
+        assert_eq!(leaves.names, ["x", "kg"]);
+        assert_eq!(leaves.total, 5);
+    }
+
+    #[test]
+    fn visitor_mut_skips_overridden_nodes() {
+        let mut start = parse("(x) in kg; -y;").unwrap();
+        Uppercase.visit_start_mut(&mut start);
+        assert_eq!(leaves(&start).names, ["X", "kg", "Y"]);
+    }
+
+    #[test]
+    fn fold_rebuilds_the_tree() {
+        let start = parse("--4; -(-x); 1 in kg;").unwrap();
+        let start = NegateNumbers.fold_start(start);
+        assert_eq!(leaves(&start).total, 5);
+        assert!(matches!(start.statements[0].value, Expression::Number(4)));
+        assert!(matches!(start.statements[1].value, Expression::Negated(_)));
+        assert_eq!(start.statements[2].unit.as_ref().unwrap().name, "kg");
+    }
+}
@@ -1,30 +1,121 @@
 extern crate regex;
 
+use super::lookahead::{Lookahead, Terminal};
+use indexmap::IndexMap;
+use pseudo_bnf_runtime::{Lexer, Span, TokenStream};
 use regex::Regex;
-use std::collections::HashMap;
+use std::collections::HashSet;
 use std::fmt;
-use std::fs::File;
-use std::io::BufRead;
-use std::io::BufReader;
+use std::fs;
+
+#[derive(Debug, PartialEq)]
+pub enum Repetition {
+    ZeroOrMore,
+    OneOrMore,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TrailingSeparator {
+    Forbidden,
+    Allowed,
+    Required,
+}
+
+#[derive(Debug)]
+pub struct Separator {
+    pub token: String,
+    pub trailing: TrailingSeparator,
+}
 
 #[derive(Debug)]
 pub enum RuleAction {
     ConsumeToken(String),
-    RuleName(String),
-    RepeatedRuleNameWithSeparator { rule_name: String, separator: String },
+    OptionalToken {
+        token: String,
+        label: Option<String>,
+    },
+    RuleName {
+        rule_name: String,
+        label: Option<String>,
+    },
+    OptionalRuleName {
+        rule_name: String,
+        label: Option<String>,
+    },
+    RepeatedRuleName {
+        rule_name: String,
+        label: Option<String>,
+        repetition: Repetition,
+        separator: Option<Separator>,
+    },
     Unknown(String),
 }
 
+impl RuleAction {
+    // The field that holds this action's value: the label if there is one, and
+    // otherwise the referenced rule name in lowercase. Unlabeled optional tokens
+    // are named after the token instead.
+    pub fn field_label(&self) -> Option<String> {
+        match self {
+            RuleAction::OptionalToken { label, .. } => label.clone(),
+            RuleAction::RuleName { rule_name, label }
+            | RuleAction::OptionalRuleName { rule_name, label }
+            | RuleAction::RepeatedRuleName {
+                rule_name, label, ..
+            } => Some(label.clone().unwrap_or_else(|| {
+                rule_name
+                    .trim_matches(|c| c == '<' || c == '>')
+                    .to_lowercase()
+            })),
+            _ => None,
+        }
+    }
+}
+
+fn write_label(f: &mut fmt::Formatter, label: &Option<String>) -> fmt::Result {
+    match label {
+        Some(label) => write!(f, "{}:", label),
+        None => Ok(()),
+    }
+}
+
 impl fmt::Display for RuleAction {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             RuleAction::ConsumeToken(token) => write!(f, r#"ConsumeToken: "{}""#, token),
-            RuleAction::RuleName(rule_name) => write!(f, "RuleName: {}", rule_name),
-            RuleAction::RepeatedRuleNameWithSeparator { rule_name, separator }=> write!(
-                f,
-                r#"RepeatedRuleNameWithSeparator "{}"-separated sub-rule: {}"#,
-                separator, rule_name
-            ),
+            RuleAction::OptionalToken { token, label } => {
+                write!(f, "OptionalToken: ")?;
+                write_label(f, label)?;
+                write!(f, r#""{}""#, token)
+            }
+            RuleAction::RuleName { rule_name, label } => {
+                write!(f, "RuleName: ")?;
+                write_label(f, label)?;
+                write!(f, "{}", rule_name)
+            }
+            RuleAction::OptionalRuleName { rule_name, label } => {
+                write!(f, "OptionalRuleName: ")?;
+                write_label(f, label)?;
+                write!(f, "{}", rule_name)
+            }
+            RuleAction::RepeatedRuleName {
+                rule_name,
+                label,
+                repetition,
+                separator,
+            } => {
+                write!(f, "RepeatedRuleName {:?} sub-rule: ", repetition)?;
+                write_label(f, label)?;
+                write!(f, "{}", rule_name)?;
+                if let Some(separator) = separator {
+                    write!(
+                        f,
+                        r#" separated by "{}" (trailing separator {:?})"#,
+                        separator.token, separator.trailing
+                    )?;
+                }
+                Ok(())
+            }
             RuleAction::Unknown(token) => write!(f, "Unknown: {}", token),
         }
     }
@@ -32,7 +123,7 @@ impl fmt::Display for RuleAction {
 
 #[derive(Debug)]
 pub enum RuleType {
-    UserSpecifiedName,
+    Terminal(String),
     RuleChoice(Vec<String>),
     Actions(Vec<RuleAction>),
 }
@@ -41,24 +132,25 @@ pub enum RuleType {
 pub struct Rule {
     pub rule_name: String,
     pub rule_type: RuleType,
+    pub line: usize,
 }
 
 impl fmt::Display for Rule {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "rule: {}", self.rule_name)?;
+        write!(f, "rule: {} (line {})", self.rule_name, self.line)?;
         match &self.rule_type {
-            RuleType::UserSpecifiedName => write!(f, " read user-specified-name\n"),
+            RuleType::Terminal(class_name) => writeln!(f, " read terminal {}", class_name),
             RuleType::RuleChoice(sub_rule_names) => {
-                write!(f, " one of the following sub-rules:\n")?;
+                writeln!(f, " one of the following sub-rules:")?;
                 for r in sub_rule_names.iter() {
-                    write!(f, "  {}\n", r)?;
+                    writeln!(f, "  {}", r)?;
                 }
                 Ok(())
             }
             RuleType::Actions(actions) => {
-                write!(f, " action sequence:\n")?;
+                writeln!(f, " action sequence:")?;
                 for action in actions.iter() {
-                    write!(f, "  {}\n", action)?;
+                    writeln!(f, "  {}", action)?;
                 }
                 Ok(())
             }
@@ -66,136 +158,953 @@ impl fmt::Display for Rule {
     }
 }
 
-fn parse_grammar_rule(line: &str) -> Rule {
-    let tokens: Vec<&str> = line.split_whitespace().collect();
-    let rule_name = tokens[0].to_string();
-    let right_hand = &tokens[2..];
-    let string_pattern = Regex::new(r#"^"([^"]+)"$"#).unwrap();
-    let rule_pattern = Regex::new(r#"^(<[A-Z-]+>)$"#).unwrap();
-    let repeated_rule_pattern = Regex::new(r#"^(<[A-Z-]+>)\*"(.)"$"#).unwrap();
+#[derive(Debug)]
+enum NotationToken {
+    Label(String),
+    Quoted(String),
+    RuleName(String),
+    Terminal(String),
+    OpenGroup,
+    CloseGroup,
+    Alternative,
+    Optional,
+    Repeated(Repetition, Option<Separator>),
+    Unknown(String),
+}
 
-    if right_hand == ["<<USER-SPECIFIED-NAME>>"] {
-        return Rule {
-            rule_name,
-            rule_type: RuleType::UserSpecifiedName,
+fn tokenize_notation(text: &str) -> Vec<NotationToken> {
+    let label_pattern = Regex::new(r#"^([a-z][a-z0-9-]*):"#).unwrap();
+    let quoted_pattern = Regex::new(r#"^"([^"]+)""#).unwrap();
+    let terminal_pattern = Regex::new(r#"^<<[A-Z-]+>>"#).unwrap();
+    let rule_pattern = Regex::new(r#"^<[A-Z-]+>"#).unwrap();
+    let repeated_pattern = Regex::new(r#"^([*+])(?:"([^"]+)"([?!]?))?"#).unwrap();
+    let unknown_pattern = Regex::new(r#"^\S+"#).unwrap();
+
+    let mut tokens = vec![];
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        let length;
+        if let Some(captures) = label_pattern.captures(rest) {
+            tokens.push(NotationToken::Label(captures[1].to_string()));
+            length = captures[0].len();
+        } else if let Some(captures) = quoted_pattern.captures(rest) {
+            tokens.push(NotationToken::Quoted(captures[1].to_string()));
+            length = captures[0].len();
+        } else if let Some(m) = terminal_pattern.find(rest) {
+            tokens.push(NotationToken::Terminal(m.as_str().to_string()));
+            length = m.end();
+        } else if let Some(m) = rule_pattern.find(rest) {
+            tokens.push(NotationToken::RuleName(m.as_str().to_string()));
+            length = m.end();
+        } else if let Some(captures) = repeated_pattern.captures(rest) {
+            let repetition = match &captures[1] {
+                "+" => Repetition::OneOrMore,
+                _ => Repetition::ZeroOrMore,
+            };
+            let separator = captures.get(2).map(|separator| Separator {
+                token: separator.as_str().to_string(),
+                trailing: match &captures[3] {
+                    "?" => TrailingSeparator::Allowed,
+                    "!" => TrailingSeparator::Required,
+                    _ => TrailingSeparator::Forbidden,
+                },
+            });
+            tokens.push(NotationToken::Repeated(repetition, separator));
+            length = captures[0].len();
+        } else if rest.starts_with('(') {
+            tokens.push(NotationToken::OpenGroup);
+            length = 1;
+        } else if rest.starts_with(')') {
+            tokens.push(NotationToken::CloseGroup);
+            length = 1;
+        } else if rest.starts_with('|') {
+            tokens.push(NotationToken::Alternative);
+            length = 1;
+        } else if rest.starts_with('?') {
+            tokens.push(NotationToken::Optional);
+            length = 1;
+        } else {
+            let m = unknown_pattern.find(rest).unwrap();
+            tokens.push(NotationToken::Unknown(m.as_str().to_string()));
+            length = m.end();
+        }
+        rest = rest[length..].trim_start();
+    }
+    tokens
+}
+
+#[derive(Debug)]
+enum Expression {
+    Token(String),
+    RuleName(String),
+    Terminal(String),
+    Group(Vec<Vec<Item>>),
+    Unknown(String),
+}
+
+#[derive(Debug)]
+enum Postfix {
+    Optional,
+    Repeated(Repetition, Option<Separator>),
+}
+
+#[derive(Debug)]
+struct Item {
+    label: Option<String>,
+    expression: Expression,
+    postfix: Option<Postfix>,
+}
+
+fn parse_alternatives(tokens: &mut Vec<NotationToken>) -> Result<Vec<Vec<Item>>, String> {
+    let mut alternatives = vec![parse_sequence(tokens)?];
+    while let Some(NotationToken::Alternative) = tokens.first() {
+        tokens.remove(0);
+        alternatives.push(parse_sequence(tokens)?);
+    }
+    Ok(alternatives)
+}
+
+fn parse_sequence(tokens: &mut Vec<NotationToken>) -> Result<Vec<Item>, String> {
+    let mut items = vec![];
+    loop {
+        let label = match tokens.first() {
+            Some(NotationToken::Label(_)) => match tokens.remove(0) {
+                NotationToken::Label(label) => Some(label),
+                _ => unreachable!(),
+            },
+            _ => None,
         };
-    } else if right_hand.iter().any(|&x| x == "|") {
-        let sub_rule_names = right_hand
-            .iter()
-            .cloned()
-            .filter(|&x| x != "|")
-            .map(|x| x.to_string())
-            .collect();
-        return Rule {
-            rule_name,
-            rule_type: RuleType::RuleChoice(sub_rule_names),
+        if let Some(label) = &label {
+            match tokens.first() {
+                Some(NotationToken::RuleName(_)) | Some(NotationToken::OpenGroup) => {}
+                Some(NotationToken::Quoted(_))
+                    if matches!(tokens.get(1), Some(NotationToken::Optional)) => {}
+                _ => {
+                    return Err(format!(
+                        "label {}: must be followed by a rule, a group or an optional \"token\"?",
+                        label
+                    ))
+                }
+            }
+        }
+        let expression = match tokens.first() {
+            None | Some(NotationToken::Alternative) | Some(NotationToken::CloseGroup) => break,
+            _ => match tokens.remove(0) {
+                NotationToken::Quoted(token) => Expression::Token(token),
+                NotationToken::RuleName(rule_name) => Expression::RuleName(rule_name),
+                NotationToken::Terminal(terminal) => Expression::Terminal(terminal),
+                NotationToken::OpenGroup => {
+                    let alternatives = parse_alternatives(tokens)?;
+                    if let Some(NotationToken::CloseGroup) = tokens.first() {
+                        tokens.remove(0);
+                        Expression::Group(alternatives)
+                    } else {
+                        return Err(r#"missing ")" to close group"#.to_string());
+                    }
+                }
+                NotationToken::Optional => Expression::Unknown("?".to_string()),
+                NotationToken::Repeated(_, _) => Expression::Unknown("*".to_string()),
+                NotationToken::Unknown(token) => Expression::Unknown(token),
+                NotationToken::Label(label) => Expression::Unknown(format!("{}:", label)),
+                NotationToken::Alternative | NotationToken::CloseGroup => unreachable!(),
+            },
         };
+        let postfix = match tokens.first() {
+            Some(NotationToken::Optional) => {
+                tokens.remove(0);
+                Some(Postfix::Optional)
+            }
+            Some(NotationToken::Repeated(_, _)) => match tokens.remove(0) {
+                NotationToken::Repeated(repetition, separator) => {
+                    Some(Postfix::Repeated(repetition, separator))
+                }
+                _ => unreachable!(),
+            },
+            _ => None,
+        };
+        items.push(Item {
+            label,
+            expression,
+            postfix,
+        });
+    }
+    Ok(items)
+}
+
+fn synthesized_rule_name(rule_name: &str, kind: &str, index: usize) -> String {
+    format!(
+        "<{}-{}-{}>",
+        rule_name.trim_matches(|c| c == '<' || c == '>'),
+        kind,
+        index
+    )
+}
+
+fn single_rule_name(sequence: &[Item]) -> Option<String> {
+    match sequence {
+        [Item {
+            label: None,
+            expression: Expression::RuleName(rule_name),
+            postfix: None,
+        }] => Some(rule_name.clone()),
+        _ => None,
     }
+}
 
-    let mut actions = Vec::new();
-    for &token in right_hand.iter() {
-        if string_pattern.is_match(token) {
-            let consume_token = string_pattern.captures(token).unwrap()[1].to_string();
-            actions.push(RuleAction::ConsumeToken(consume_token));
-        } else if rule_pattern.is_match(token) {
-            let rule_name = rule_pattern.captures(token).unwrap()[1].to_string();
-            actions.push(RuleAction::RuleName(rule_name));
-        } else if repeated_rule_pattern.is_match(token) {
-            let rule_name = repeated_rule_pattern.captures(token).unwrap()[1].to_string();
-            let separator = repeated_rule_pattern.captures(token).unwrap()[2].to_string();
-            actions.push(RuleAction::RepeatedRuleNameWithSeparator { rule_name, separator });
-        } else {
-            actions.push(RuleAction::Unknown(token.to_string()));
+fn lower_rule(
+    rule_name: String,
+    mut alternatives: Vec<Vec<Item>>,
+    line: usize,
+    rules: &mut Vec<Rule>,
+) {
+    if alternatives.len() > 1 {
+        let mut sub_rule_names = vec![];
+        for (i, sequence) in alternatives.into_iter().enumerate() {
+            if let Some(sub_rule_name) = single_rule_name(&sequence) {
+                sub_rule_names.push(sub_rule_name);
+            } else {
+                let sub_rule_name = synthesized_rule_name(&rule_name, "ALTERNATIVE", i + 1);
+                lower_rule(sub_rule_name.clone(), vec![sequence], line, rules);
+                sub_rule_names.push(sub_rule_name);
+            }
         }
+        rules.push(Rule {
+            rule_name,
+            rule_type: RuleType::RuleChoice(sub_rule_names),
+            line,
+        });
+        return;
     }
-    return Rule {
+
+    let sequence = alternatives.remove(0);
+    if let [Item {
+        label: None,
+        expression: Expression::Terminal(terminal),
+        postfix: None,
+    }] = sequence.as_slice()
+    {
+        rules.push(Rule {
+            rule_name,
+            rule_type: RuleType::Terminal(terminal.clone()),
+            line,
+        });
+        return;
+    }
+
+    let mut actions = vec![];
+    let mut group_count = 0;
+    for item in sequence {
+        let sub_rule_name = match item.expression {
+            Expression::Token(token) => {
+                actions.push(match item.postfix {
+                    None => RuleAction::ConsumeToken(token),
+                    Some(Postfix::Optional) => RuleAction::OptionalToken {
+                        token,
+                        label: item.label,
+                    },
+                    Some(Postfix::Repeated(_, _)) => RuleAction::Unknown(format!("{:?}*", token)),
+                });
+                continue;
+            }
+            Expression::Terminal(terminal) => {
+                actions.push(RuleAction::Unknown(terminal));
+                continue;
+            }
+            Expression::Unknown(token) => {
+                actions.push(RuleAction::Unknown(token));
+                continue;
+            }
+            Expression::RuleName(sub_rule_name) => sub_rule_name,
+            Expression::Group(group) => match group.as_slice() {
+                [sequence] => single_rule_name(sequence),
+                _ => None,
+            }
+            .unwrap_or_else(|| {
+                group_count += 1;
+                let sub_rule_name = synthesized_rule_name(&rule_name, "GROUP", group_count);
+                lower_rule(sub_rule_name.clone(), group, line, rules);
+                sub_rule_name
+            }),
+        };
+        let label = item.label;
+        actions.push(match item.postfix {
+            None => RuleAction::RuleName {
+                rule_name: sub_rule_name,
+                label,
+            },
+            Some(Postfix::Optional) => RuleAction::OptionalRuleName {
+                rule_name: sub_rule_name,
+                label,
+            },
+            Some(Postfix::Repeated(repetition, separator)) => RuleAction::RepeatedRuleName {
+                rule_name: sub_rule_name,
+                label,
+                repetition,
+                separator,
+            },
+        });
+    }
+    rules.push(Rule {
         rule_name,
         rule_type: RuleType::Actions(actions),
+        line,
+    });
+}
+
+// Skips over "quoted tokens" and /regex patterns/, where a backslash escapes
+// the closing slash.
+fn find_outside_quotes(text: &str, pattern: &str) -> Option<usize> {
+    let mut quote = None;
+    let mut escaped = false;
+    for (position, c) in text.char_indices() {
+        match quote {
+            Some('/') if escaped => escaped = false,
+            Some('/') if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '/' => quote = Some(c),
+            None if text[position..].starts_with(pattern) => return Some(position),
+            None => {}
+        }
+    }
+    None
+}
+
+// Parses trait paths such as "Clone, PartialEq serde::Serialize", separated by
+// commas or whitespace.
+pub fn parse_derive_list(text: &str) -> Result<Vec<String>, String> {
+    let path_pattern =
+        Regex::new(r#"^[A-Za-z_][A-Za-z0-9_]*(::[A-Za-z_][A-Za-z0-9_]*)*$"#).unwrap();
+    let derives: Vec<String> = text
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|derive| !derive.is_empty())
+        .map(|derive| derive.to_string())
+        .collect();
+    if derives.is_empty() {
+        return Err("expected at least one trait to derive".to_string());
+    }
+    match derives.iter().find(|derive| !path_pattern.is_match(derive)) {
+        Some(derive) => Err(format!("invalid trait to derive {:?}", derive)),
+        None => Ok(derives),
+    }
+}
+
+const RULE_NAME_PATTERN: &str = r#"^(<[A-Z-]+>|<<START>>)$"#;
+
+fn parse_grammar_rule(text: &str, line: usize) -> Result<Vec<Rule>, GrammarError> {
+    let error = |message: String| GrammarError { line, message };
+    let position = find_outside_quotes(text, "::=").unwrap();
+    let left_hand = text[..position].trim();
+    let rule_name_pattern = Regex::new(RULE_NAME_PATTERN).unwrap();
+    if !rule_name_pattern.is_match(left_hand) {
+        return Err(error(format!("invalid rule name {:?}", left_hand)));
+    }
+    let mut tokens = tokenize_notation(&text[position + 3..]);
+    let alternatives = parse_alternatives(&mut tokens).map_err(error)?;
+    if !tokens.is_empty() {
+        return Err(error(
+            r#"unexpected ")" without a matching "(""#.to_string(),
+        ));
+    }
+    let mut rules = vec![];
+    lower_rule(left_hand.to_string(), alternatives, line, &mut rules);
+    Ok(rules)
+}
+
+fn parse_terminal_class(text: &str, line: usize) -> Result<TerminalClass, GrammarError> {
+    let error = |message: String| GrammarError { line, message };
+    let position = find_outside_quotes(text, "~=").unwrap();
+    let name = text[..position].trim();
+    let class_name_pattern = Regex::new(r#"^<<[A-Z-]+>>$"#).unwrap();
+    if !class_name_pattern.is_match(name) || name == "<<START>>" || name == "<<EOF>>" {
+        return Err(error(format!("invalid terminal class name {:?}", name)));
+    }
+    let definition = text[position + 2..].trim();
+    let pattern = match definition
+        .strip_prefix('/')
+        .and_then(|d| d.strip_suffix('/'))
+    {
+        Some(pattern) if !pattern.is_empty() => pattern.replace("\\/", "/"),
+        _ => {
+            return Err(error(format!(
+                "expected a /pattern/ for terminal class {}, found {:?}",
+                name, definition
+            )))
+        }
     };
+    match Regex::new(&format!("^(?:{})$", pattern)) {
+        Ok(re) if re.is_match("") => Err(error(format!(
+            "pattern for terminal class {} matches the empty string",
+            name
+        ))),
+        Ok(_) => Ok(TerminalClass {
+            name: name.to_string(),
+            pattern,
+            kind: TerminalKind::of(name),
+            line,
+        }),
+        Err(e) => Err(error(format!(
+            "invalid pattern for terminal class {}: {}",
+            name, e
+        ))),
+    }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TerminalKind {
+    Text,
+    Integer,
+    Float,
+    StringLiteral,
+}
+
+impl TerminalKind {
+    fn of(class_name: &str) -> TerminalKind {
+        match class_name {
+            "<<INTEGER>>" => TerminalKind::Integer,
+            "<<FLOAT>>" => TerminalKind::Float,
+            "<<STRING-LITERAL>>" => TerminalKind::StringLiteral,
+            _ => TerminalKind::Text,
+        }
+    }
+}
+
+// Classes that grammars may use without declaring them. A declaration with the
+// same name replaces the default pattern but keeps the value type.
+const BUILT_IN_TERMINAL_CLASSES: &[(&str, &str)] = &[
+    ("<<USER-SPECIFIED-NAME>>", "[A-Z][a-zA-Z-]*"),
+    ("<<INTEGER>>", "[0-9]+"),
+    ("<<FLOAT>>", r"[0-9]+\.[0-9]+(?:[eE][-+]?[0-9]+)?"),
+    ("<<STRING-LITERAL>>", r#""(?:[^"\\]|\\.)*""#),
+];
+
+// Line 0 marks a built-in class that the grammar did not declare.
 #[derive(Debug)]
-pub struct Grammar {
-    pub rules: HashMap<String, Rule>,
+pub struct TerminalClass {
+    pub name: String,
+    pub pattern: String,
+    pub kind: TerminalKind,
+    pub line: usize,
 }
 
-impl fmt::Display for Grammar {
+#[derive(Debug)]
+pub struct GrammarError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for GrammarError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut keys: Vec<&String> = self.rules.keys().collect();
-        keys.sort();
-        for k in keys.iter() {
-            write!(f, "{}", self.rules.get(*k).unwrap())?;
+        // Line 0 is used for problems with the grammar as a whole.
+        if self.line == 0 {
+            return write!(f, "{}", self.message);
         }
-        Ok(())
+        write!(f, "line {}: {}", self.line, self.message)
     }
 }
 
-pub fn read_grammar_from_file(filename: &str) -> Grammar {
-    // println!("Reading grammar from file {}", filename);
-    let file = File::open(filename).expect("Could not read grammar");
-    let reader = BufReader::new(&file);
-    let mut rules = HashMap::new();
-    for line in reader.lines() {
-        let s = line.unwrap();
-        let rule = parse_grammar_rule(&s);
-        rules.insert(rule.rule_name.clone(), rule);
-    }
-    Grammar { rules }
+#[derive(Debug)]
+pub struct Grammar {
+    // In source order, with rules synthesized from groups and alternatives
+    // following the rule they came from.
+    pub rules: IndexMap<String, Rule>,
+    pub duplicate_rules: Vec<(String, usize)>,
+    // The rule named by a %start directive and the directive's line.
+    pub start: Option<(String, usize)>,
+    // Extra derives and field visibility from %derive and %pub-fields.
+    pub derives: Vec<String>,
+    pub pub_fields: bool,
+    // In tokenizer priority order: declared classes first, then any built-in
+    // classes the rules use.
+    pub terminal_classes: Vec<TerminalClass>,
 }
 
-fn consume(expected: &str, tokens: &mut Vec<&str>, indent: &str) {
-    if tokens[0] == expected {
-        // println!(r#"{}consumed "{}"!"#, indent, tokens[0]);
-        tokens.remove(0);
-    // println!(r#"{}next is "{}"!"#, indent, tokens[0]);
-    } else {
-        println!(
-            "{}SYNTAX ERROR: expected {} but found {}!",
-            indent, expected, tokens[0]
-        );
+impl Rule {
+    fn referenced_rule_names(&self) -> Vec<&String> {
+        match &self.rule_type {
+            RuleType::Terminal(_) => vec![],
+            RuleType::RuleChoice(sub_rule_names) => sub_rule_names.iter().collect(),
+            RuleType::Actions(actions) => actions
+                .iter()
+                .filter_map(|action| match action {
+                    RuleAction::RuleName { rule_name, .. }
+                    | RuleAction::OptionalRuleName { rule_name, .. }
+                    | RuleAction::RepeatedRuleName { rule_name, .. } => Some(rule_name),
+                    _ => None,
+                })
+                .collect(),
+        }
     }
 }
 
-fn check_if_can_start_rule(grammar: &Grammar, rule_name: &str, token: &str, indent: &str) -> bool {
-    let rule = grammar.rules.get(rule_name).unwrap();
-    match &rule.rule_type {
-        RuleType::Actions(actions) => {
-            let first_action = &actions[0];
-            // println!(r#"{}try to match {}"#, indent, first_action);
-            match first_action {
-                RuleAction::ConsumeToken(consume_token) => consume_token == token,
-                _ => {
-                    println!(
-                        r#"{}SYNTAX ERROR: check_if_can_start_rule at "{}""#,
-                        indent, token
-                    );
-                    println!("{}reason: first action must be consuming a token", indent);
-                    false
+impl Grammar {
+    fn add_rules(&mut self, mut rules: Vec<Rule>) {
+        let parent = rules.pop().unwrap();
+        if self.rules.contains_key(&parent.rule_name) {
+            self.duplicate_rules.push((parent.rule_name, parent.line));
+            return;
+        }
+        self.rules.insert(parent.rule_name.clone(), parent);
+        for rule in rules {
+            if self.rules.contains_key(&rule.rule_name) {
+                self.duplicate_rules.push((rule.rule_name, rule.line));
+            } else {
+                self.rules.insert(rule.rule_name.clone(), rule);
+            }
+        }
+    }
+
+    fn add_directive(&mut self, text: &str, line: usize) -> Result<(), GrammarError> {
+        let error = |message: String| GrammarError { line, message };
+        let words: Vec<&str> = text.split_whitespace().collect();
+        match words.as_slice() {
+            ["%start", rule_name] => {
+                let rule_name_pattern = Regex::new(RULE_NAME_PATTERN).unwrap();
+                if !rule_name_pattern.is_match(rule_name) {
+                    return Err(error(format!("invalid rule name {:?}", rule_name)));
+                }
+                if let Some((_, first_line)) = &self.start {
+                    return Err(error(format!(
+                        "duplicate %start directive (first given on line {})",
+                        first_line
+                    )));
                 }
+                self.start = Some((rule_name.to_string(), line));
+                Ok(())
             }
+            ["%start", ..] => Err(error("expected %start <RULE-NAME>".to_string())),
+            ["%derive", ..] => {
+                let derives =
+                    parse_derive_list(&text.trim_start()["%derive".len()..]).map_err(error)?;
+                self.derives.extend(derives);
+                Ok(())
+            }
+            ["%pub-fields"] => {
+                self.pub_fields = true;
+                Ok(())
+            }
+            ["%pub-fields", ..] => Err(error("%pub-fields takes no arguments".to_string())),
+            _ => Err(error(format!("unknown directive {:?}", words[0]))),
+        }
+    }
+
+    // Grammars without a %start directive start at <<START>> if they define it.
+    pub fn start_rule_name(&self) -> Option<&str> {
+        match &self.start {
+            Some((rule_name, _)) => Some(rule_name),
+            None if self.rules.contains_key("<<START>>") => Some("<<START>>"),
+            None => None,
         }
-        RuleType::UserSpecifiedName => {
-            let user_specified_name_pattern = Regex::new(r#"^[A-Z][a-zA-Z-]*$"#).unwrap();
-            user_specified_name_pattern.is_match(token)
+    }
+
+    fn add_terminal_class(&mut self, class: TerminalClass) -> Result<(), GrammarError> {
+        if let Some(existing) = self.terminal_class(&class.name) {
+            return Err(GrammarError {
+                line: class.line,
+                message: format!(
+                    "duplicate definition of {} (first defined on line {})",
+                    class.name, existing.line
+                ),
+            });
         }
-        _ => {
-            println!(
-                r#"{}SYNTAX ERROR: check_if_can_start_rule at "{}""#,
-                indent, token
+        self.terminal_classes.push(class);
+        Ok(())
+    }
+
+    fn add_built_in_terminal_classes(&mut self) {
+        let mut used: Vec<&str> = self
+            .rules
+            .values()
+            .filter_map(|rule| match &rule.rule_type {
+                RuleType::Terminal(class_name) => Some(class_name.as_str()),
+                _ => None,
+            })
+            .collect();
+        used.sort_unstable();
+        for (name, pattern) in BUILT_IN_TERMINAL_CLASSES {
+            if used.binary_search(name).is_ok() && self.terminal_class(name).is_none() {
+                self.terminal_classes.push(TerminalClass {
+                    name: name.to_string(),
+                    pattern: pattern.to_string(),
+                    kind: TerminalKind::of(name),
+                    line: 0,
+                });
+            }
+        }
+    }
+
+    pub fn terminal_class(&self, class_name: &str) -> Option<&TerminalClass> {
+        self.terminal_classes.iter().find(|c| c.name == class_name)
+    }
+
+    fn is_synthesized_from(&self, sub_rule_name: &str, rule_name: &str) -> bool {
+        sub_rule_name != rule_name
+            && self.rules.get(sub_rule_name).map(|r| r.line)
+                == self.rules.get(rule_name).map(|r| r.line)
+    }
+
+    // Rewrites <A> ::= <A> a1 | ... | <A> an | b1 | ... | bm
+    // into     <A> ::= <A-HEAD> <A-TAIL>*
+    // with     <A-HEAD> ::= b1 | ... | bm and <A-TAIL> ::= a1 | ... | an.
+    pub fn rewrite_direct_left_recursion(&mut self) {
+        let rule_names: Vec<String> = self.rules.keys().cloned().collect();
+        for rule_name in rule_names {
+            // Combining alternatives renames synthesized rules that come later.
+            let Some(rule) = self.rules.get(&rule_name) else {
+                continue;
+            };
+            let line = rule.line;
+            let sub_rule_names = match &rule.rule_type {
+                RuleType::RuleChoice(sub_rule_names) => sub_rule_names.clone(),
+                _ => continue,
+            };
+            let (tails, heads): (Vec<String>, Vec<String>) =
+                sub_rule_names.into_iter().partition(|sub_rule_name| {
+                    self.is_synthesized_from(sub_rule_name, &rule_name)
+                        && match &self.rules[sub_rule_name].rule_type {
+                            RuleType::Actions(actions) => match actions.first() {
+                                Some(RuleAction::RuleName {
+                                    rule_name: first, ..
+                                }) => *first == rule_name,
+                                _ => false,
+                            },
+                            _ => false,
+                        }
+                });
+            if tails.is_empty() || heads.is_empty() {
+                continue;
+            }
+            for tail in &tails {
+                if let RuleType::Actions(actions) = &mut self.rules.get_mut(tail).unwrap().rule_type
+                {
+                    actions.remove(0);
+                }
+            }
+            let head = self.combine_alternatives(&rule_name, "HEAD", heads, line);
+            let tail = self.combine_alternatives(&rule_name, "TAIL", tails, line);
+            self.rules.insert(
+                rule_name.clone(),
+                Rule {
+                    rule_name,
+                    rule_type: RuleType::Actions(vec![
+                        RuleAction::RuleName {
+                            rule_name: head,
+                            label: None,
+                        },
+                        RuleAction::RepeatedRuleName {
+                            rule_name: tail,
+                            label: None,
+                            repetition: Repetition::ZeroOrMore,
+                            separator: None,
+                        },
+                    ]),
+                    line,
+                },
             );
-            println!(
-                "{}reason: only action sequences and user-specified-names are supported",
-                indent
+        }
+    }
+
+    fn combine_alternatives(
+        &mut self,
+        rule_name: &str,
+        kind: &str,
+        mut alternatives: Vec<String>,
+        line: usize,
+    ) -> String {
+        let combined_name = format!(
+            "<{}-{}>",
+            rule_name.trim_matches(|c| c == '<' || c == '>'),
+            kind
+        );
+        if alternatives.len() == 1 {
+            let alternative = alternatives.remove(0);
+            if !self.is_synthesized_from(&alternative, rule_name) {
+                return alternative;
+            }
+            let mut rule = self.rules.shift_remove(&alternative).unwrap();
+            rule.rule_name = combined_name.clone();
+            self.rules.insert(combined_name.clone(), rule);
+        } else {
+            self.rules.insert(
+                combined_name.clone(),
+                Rule {
+                    rule_name: combined_name.clone(),
+                    rule_type: RuleType::RuleChoice(alternatives),
+                    line,
+                },
             );
-            false
         }
+        combined_name
+    }
+
+    pub fn validate(&self) -> Result<(), Vec<GrammarError>> {
+        let mut errors = vec![];
+        for (rule_name, line) in &self.duplicate_rules {
+            errors.push(GrammarError {
+                line: *line,
+                message: format!(
+                    "duplicate definition of {} (first defined on line {})",
+                    rule_name, self.rules[rule_name].line
+                ),
+            });
+        }
+        for rule in self.rules.values() {
+            for sub_rule_name in rule.referenced_rule_names() {
+                if !self.rules.contains_key(sub_rule_name) {
+                    errors.push(GrammarError {
+                        line: rule.line,
+                        message: format!(
+                            "undefined rule {} referenced from {}",
+                            sub_rule_name, rule.rule_name
+                        ),
+                    });
+                }
+            }
+            if let RuleType::Terminal(class_name) = &rule.rule_type {
+                if self.terminal_class(class_name).is_none() {
+                    errors.push(GrammarError {
+                        line: rule.line,
+                        message: format!(
+                            "unknown terminal class {} in {} (declare it with {} ~= /pattern/)",
+                            class_name, rule.rule_name, class_name
+                        ),
+                    });
+                }
+            }
+            if let RuleType::Actions(actions) = &rule.rule_type {
+                let mut field_labels = HashSet::new();
+                for action in actions {
+                    if let Some(field_label) = action.field_label() {
+                        if !field_labels.insert(field_label.clone()) {
+                            errors.push(GrammarError {
+                                line: rule.line,
+                                message: format!(
+                                    "field {} appears more than once in {}; give each use a distinct label:<RULE>",
+                                    field_label, rule.rule_name
+                                ),
+                            });
+                        }
+                    }
+                }
+                for action in actions {
+                    if let RuleAction::Unknown(token) = action {
+                        errors.push(GrammarError {
+                            line: rule.line,
+                            message: format!("unknown token {} in {}", token, rule.rule_name),
+                        });
+                    }
+                }
+            }
+        }
+        if let Some((rule_name, line)) = &self.start {
+            if !self.rules.contains_key(rule_name) {
+                errors.push(GrammarError {
+                    line: *line,
+                    message: format!("undefined start rule {}", rule_name),
+                });
+            }
+        }
+        if self.rules.is_empty() {
+            errors.push(GrammarError {
+                line: 0,
+                message: "the grammar has no rules".to_string(),
+            });
+        } else if self.start_rule_name().is_none() {
+            errors.push(GrammarError {
+                line: 0,
+                message: "missing start rule (add %start <RULE>)".to_string(),
+            });
+        }
+        let start_rule_name = self
+            .start_rule_name()
+            .filter(|rule_name| self.rules.contains_key(*rule_name));
+        if let Some(start_rule_name) = start_rule_name {
+            let mut reachable = HashSet::new();
+            let mut pending = vec![start_rule_name];
+            while let Some(rule_name) = pending.pop() {
+                if let Some(rule) = self.rules.get(rule_name) {
+                    if reachable.insert(rule_name) {
+                        pending.extend(rule.referenced_rule_names().iter().map(|r| r.as_str()));
+                    }
+                }
+            }
+            // Rules synthesized from groups are reported through their parent rule.
+            let mut synthesized = HashSet::new();
+            for rule in self.rules.values() {
+                for sub_rule_name in rule.referenced_rule_names() {
+                    if *sub_rule_name != rule.rule_name
+                        && self.rules.get(sub_rule_name).map(|r| r.line) == Some(rule.line)
+                    {
+                        synthesized.insert(sub_rule_name.as_str());
+                    }
+                }
+            }
+            for rule in self.rules.values() {
+                let rule_name = rule.rule_name.as_str();
+                if !reachable.contains(rule_name) && !synthesized.contains(rule_name) {
+                    errors.push(GrammarError {
+                        line: rule.line,
+                        message: format!(
+                            "rule {} is not reachable from {}",
+                            rule.rule_name, start_rule_name
+                        ),
+                    });
+                }
+            }
+        }
+        if errors.is_empty() {
+            return Ok(());
+        }
+        errors.sort_by(|a, b| (a.line, &a.message).cmp(&(b.line, &b.message)));
+        Err(errors)
+    }
+
+    // Quoted tokens are reserved, so the tokenizer never gives them a terminal
+    // class even when the class pattern matches them.
+    pub fn reserved_keyword_warnings(&self) -> Vec<GrammarError> {
+        let keywords = self.keywords();
+        let mut warnings = vec![];
+        for class in &self.terminal_classes {
+            let re = Regex::new(&format!("^(?:{})$", class.pattern)).unwrap();
+            let shadowed: Vec<String> = keywords
+                .iter()
+                .filter(|keyword| re.is_match(keyword))
+                .map(|keyword| format!("{:?}", keyword))
+                .collect();
+            if !shadowed.is_empty() {
+                let (noun, verb) = if shadowed.len() == 1 {
+                    ("keyword", "is")
+                } else {
+                    ("keywords", "are")
+                };
+                warnings.push(GrammarError {
+                    line: class.line,
+                    message: format!(
+                        "{} matches the {} {}, which {} reserved and never read as {}",
+                        class.name,
+                        noun,
+                        shadowed.join(", "),
+                        verb,
+                        class.name
+                    ),
+                });
+            }
+        }
+        warnings
+    }
+
+    pub fn keywords(&self) -> Vec<String> {
+        let mut keywords = vec![];
+        for rule in self.rules.values() {
+            if let RuleType::Actions(actions) = &rule.rule_type {
+                for action in actions {
+                    match action {
+                        RuleAction::ConsumeToken(token)
+                        | RuleAction::OptionalToken { token, .. } => keywords.push(token.clone()),
+                        RuleAction::RepeatedRuleName {
+                            separator: Some(separator),
+                            ..
+                        } => keywords.push(separator.token.clone()),
+                        _ => {}
+                    }
+                }
+            }
+        }
+        keywords.sort();
+        keywords.dedup();
+        keywords
+    }
+}
+
+impl fmt::Display for Grammar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for rule in self.rules.values() {
+            write!(f, "{}", rule)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn read_grammar_from_file(filename: &str) -> Result<Grammar, GrammarError> {
+    // println!("Reading grammar from file {}", filename);
+    let source = fs::read_to_string(filename).map_err(|error| GrammarError {
+        line: 0,
+        message: format!("could not read the grammar: {}", error),
+    })?;
+    read_grammar_from_str(&source)
+}
+
+pub fn read_grammar_from_str(source: &str) -> Result<Grammar, GrammarError> {
+    let mut grammar = Grammar {
+        rules: IndexMap::new(),
+        duplicate_rules: vec![],
+        start: None,
+        derives: vec![],
+        pub_fields: false,
+        terminal_classes: vec![],
+    };
+    let mut current_rule: Option<(usize, String)> = None;
+    for (i, s) in source.lines().enumerate() {
+        let s = match find_outside_quotes(s, "#") {
+            Some(position) => &s[..position],
+            None => s,
+        };
+        if s.trim().is_empty() {
+            continue;
+        }
+        if s.trim_start().starts_with('%') {
+            if let Some((line, text)) = current_rule.take() {
+                grammar.add_rules(parse_grammar_rule(&text, line)?);
+            }
+            grammar.add_directive(s, i + 1)?;
+        } else if find_outside_quotes(s, "~=").is_some() {
+            if let Some((line, text)) = current_rule.take() {
+                grammar.add_rules(parse_grammar_rule(&text, line)?);
+            }
+            grammar.add_terminal_class(parse_terminal_class(s, i + 1)?)?;
+        } else if find_outside_quotes(s, "::=").is_some() {
+            if let Some((line, text)) = current_rule.take() {
+                grammar.add_rules(parse_grammar_rule(&text, line)?);
+            }
+            current_rule = Some((i + 1, s.to_string()));
+        } else if let Some((_, text)) = &mut current_rule {
+            text.push(' ');
+            text.push_str(s);
+        } else {
+            return Err(GrammarError {
+                line: i + 1,
+                message: r#"expected a rule definition "<RULE-NAME> ::= ...""#.to_string(),
+            });
+        }
+    }
+    if let Some((line, text)) = current_rule {
+        grammar.add_rules(parse_grammar_rule(&text, line)?);
+    }
+    grammar.add_built_in_terminal_classes();
+    Ok(grammar)
+}
+
+fn consume(expected: &str, tokens: &mut TokenStream, indent: &str) {
+    if tokens.peek().text == expected {
+        // println!(r#"{}consumed "{}"!"#, indent, tokens.peek().text);
+        tokens.advance();
+    // println!(r#"{}next is "{}"!"#, indent, tokens.peek().text);
+    } else {
+        println!(
+            "{}SYNTAX ERROR at {}: expected {} but found {}!",
+            indent,
+            tokens.peek().span(),
+            expected,
+            tokens.peek().text
+        );
     }
 }
 
 #[derive(Debug)]
 enum DetailedActionProduction {
     Rules(Vec<DetailedRuleProduction>),
-    Consume(String),
+    Consume,
+    OptionalToken { token: String, present: bool },
     Unknown,
 }
 
@@ -204,51 +1113,97 @@ enum DetailedRuleProduction {
     RuleActionSequence {
         rule_name: String,
         actions: Vec<DetailedActionProduction>,
+        span: Span,
     },
-    UserSpecifiedName {
+    Terminal {
         rule_name: String,
-        user_specified_name: String,
+        text: String,
+        span: Span,
     },
     Error,
 }
 
 fn run_action(
     grammar: &Grammar,
+    lookahead: &Lookahead,
     action: &RuleAction,
-    tokens: &mut Vec<&str>,
+    tokens: &mut TokenStream,
     indent: &str,
 ) -> DetailedActionProduction {
     let subindent = indent.to_owned() + "  ";
     match action {
-        RuleAction::RepeatedRuleNameWithSeparator {rule_name, separator}=> {
+        RuleAction::RepeatedRuleName {
+            rule_name,
+            repetition,
+            separator,
+            ..
+        } => {
             // println!("{}run_action {}", indent, action);
+            let can_start = |tokens: &TokenStream| lookahead.can_start(rule_name, tokens.peek());
             let mut rules = vec![];
-            let rp = run_rule(grammar, rule_name, tokens, &subindent);
-            rules.push(rp);
-            while tokens[0] == separator {
-                consume(separator, tokens, &subindent);
-                let matches =
-                    check_if_can_start_rule(grammar, rule_name, tokens[0], &subindent);
-                // println!("{} - {} - {}", subindent, action.rule_name, matches);
-                if matches {
-                    let rp = run_rule(grammar, rule_name, tokens, &subindent);
-                    rules.push(rp);
-                } else {
-                    break;
-                }
+            let mut more = *repetition == Repetition::OneOrMore || can_start(tokens);
+            while more {
+                let rp = run_rule(grammar, lookahead, rule_name, tokens, &subindent);
+                rules.push(rp);
+                more = match separator {
+                    None => can_start(tokens),
+                    Some(separator) => match separator.trailing {
+                        TrailingSeparator::Forbidden => {
+                            let more = tokens.peek().text == separator.token;
+                            if more {
+                                consume(&separator.token, tokens, &subindent);
+                            }
+                            more
+                        }
+                        TrailingSeparator::Allowed => {
+                            let more = tokens.peek().text == separator.token;
+                            if more {
+                                consume(&separator.token, tokens, &subindent);
+                            }
+                            more && can_start(tokens)
+                        }
+                        TrailingSeparator::Required => {
+                            consume(&separator.token, tokens, &subindent);
+                            can_start(tokens)
+                        }
+                    },
+                };
             }
             DetailedActionProduction::Rules(rules)
-            // println!(r#"{}finished loop, next token is "{}""#, indent, tokens[0]);
+            // println!(r#"{}finished loop, next token is "{}""#, indent, tokens.peek().text);
         }
-        RuleAction::RuleName(rule_name) => {
+        RuleAction::RuleName { rule_name, .. } => {
             // println!("{}run_action {}", indent, action);
-            let rp = run_rule(grammar, rule_name, tokens, &subindent);
+            let rp = run_rule(grammar, lookahead, rule_name, tokens, &subindent);
             DetailedActionProduction::Rules(vec![rp])
         }
+        RuleAction::OptionalRuleName { rule_name, .. } => {
+            // println!("{}run_action {}", indent, action);
+            if lookahead.can_start(rule_name, tokens.peek()) {
+                let rp = run_rule(grammar, lookahead, rule_name, tokens, &subindent);
+                DetailedActionProduction::Rules(vec![rp])
+            } else {
+                DetailedActionProduction::Rules(vec![])
+            }
+        }
         RuleAction::ConsumeToken(consume_token) => {
             // println!("{}run_action {}", indent, action);
             consume(consume_token, tokens, &subindent);
-            DetailedActionProduction::Consume(consume_token.to_owned())
+            DetailedActionProduction::Consume
+        }
+        RuleAction::OptionalToken {
+            token: optional_token,
+            ..
+        } => {
+            // println!("{}run_action {}", indent, action);
+            let present = tokens.peek().text == optional_token;
+            if present {
+                consume(optional_token, tokens, &subindent);
+            }
+            DetailedActionProduction::OptionalToken {
+                token: optional_token.to_owned(),
+                present,
+            }
         }
         RuleAction::Unknown(_) => {
             println!("{}UNKNOWN ACTION! {}", indent, action);
@@ -259,47 +1214,65 @@ fn run_action(
 
 fn run_rule(
     grammar: &Grammar,
+    lookahead: &Lookahead,
     rule_name: &str,
-    tokens: &mut Vec<&str>,
+    tokens: &mut TokenStream,
     indent: &str,
 ) -> DetailedRuleProduction {
     let subindent = indent.to_owned() + "  ";
     let rule = grammar.rules.get(rule_name).unwrap();
+    let first = *tokens.peek();
     match &rule.rule_type {
         RuleType::Actions(rule_actions) => {
             let mut actions = vec![];
             // println!("{}Running rule {} action sequence!", indent, rule.name);
             for action in rule_actions.iter() {
-                let ap = run_action(grammar, action, tokens, &subindent);
+                let ap = run_action(grammar, lookahead, action, tokens, &subindent);
                 actions.push(ap);
             }
             // println!("{}Rule {} done", indent, rule.name);
             DetailedRuleProduction::RuleActionSequence {
                 rule_name: rule.rule_name.to_owned(),
                 actions,
+                span: tokens.span_from(&first),
             }
         }
-        RuleType::UserSpecifiedName => {
+        RuleType::Terminal(class_name) => {
             // println!(
-            //     r#"{}Running rule {} read user specified name: "{}""#,
-            //     indent, rule.name, tokens[0]
+            //     r#"{}Running rule {} read terminal {}: "{}""#,
+            //     indent, rule.name, class_name, tokens.peek().text
             // );
-            let user_specified_name_pattern = Regex::new(r#"^[A-Z][a-zA-Z-]*$"#).unwrap();
-            if user_specified_name_pattern.is_match(tokens[0]) {
-                let name = tokens.remove(0);
-                DetailedRuleProduction::UserSpecifiedName {
+            let terminal = Terminal::Class(class_name.clone());
+            if lookahead.matches(&terminal, tokens.peek()) {
+                let token = tokens.advance();
+                DetailedRuleProduction::Terminal {
                     rule_name: rule.rule_name.to_owned(),
-                    user_specified_name: name.to_owned(),
+                    text: token.text.to_owned(),
+                    span: token.span(),
                 }
+            } else if tokens.peek().shadowed_class.is_some()
+                && tokens.peek().shadowed_class == lookahead.class_indices.get(class_name).copied()
+            {
+                println!(
+                    r#"{}SYNTAX ERROR at {}: "{}" is a reserved keyword and cannot be used as {}"#,
+                    indent,
+                    tokens.peek().span(),
+                    tokens.peek().text,
+                    class_name
+                );
+                DetailedRuleProduction::Error
             } else {
                 println!(
-                    r#"{}SYNTAX ERROR: invalid user-specified-name at "{}""#,
-                    indent, tokens[0]
+                    r#"{}SYNTAX ERROR at {}: expected {} but found "{}""#,
+                    indent,
+                    tokens.peek().span(),
+                    class_name,
+                    tokens.peek().text
                 );
                 DetailedRuleProduction::Error
             }
-            // println!(r#"{}read name: "{}""#, subindent, tokens[0]);
-            // println!(r#"{}next is: "{}""#, subindent, tokens[0]);
+            // println!(r#"{}read name: "{}""#, subindent, name.text);
+            // println!(r#"{}next is: "{}""#, subindent, tokens.peek().text);
         }
         RuleType::RuleChoice(sub_rule_names) => {
             // println!(
@@ -309,7 +1282,10 @@ fn run_rule(
             let mut count_matches = 0;
             let mut match_rule_name = "";
             for rn in sub_rule_names.iter() {
-                let matches = check_if_can_start_rule(grammar, rn, tokens[0], &subindent);
+                let matches = lookahead
+                    .alternative_lookahead(rule_name, rn)
+                    .iter()
+                    .any(|t| lookahead.matches(t, tokens.peek()));
                 // println!("{} - {} - {}", subindent, rn, matches);
                 if matches {
                     count_matches += 1;
@@ -318,9 +1294,13 @@ fn run_rule(
             }
             if count_matches == 1 {
                 // println!("{}matched rule {}!", indent, match_rule_name);
-                run_rule(grammar, match_rule_name, tokens, &subindent)
+                run_rule(grammar, lookahead, match_rule_name, tokens, &subindent)
             } else {
-                println!("{}rule-choice syntax error", indent);
+                println!(
+                    "{}rule-choice syntax error at {}",
+                    indent,
+                    tokens.peek().span()
+                );
                 println!(
                     "{}reason: must match exactly 1 sub-rule, but matched {}",
                     indent, count_matches
@@ -335,10 +1315,16 @@ pub enum RuleProduction {
     RuleActionSequence {
         rule_name: String,
         actions: Vec<RuleProduction>,
+        span: Span,
     },
-    UserSpecifiedName {
+    Terminal {
         rule_name: String,
-        user_specified_name: String,
+        text: String,
+        span: Span,
+    },
+    OptionalToken {
+        token: String,
+        present: bool,
     },
     Error,
 }
@@ -346,27 +1332,35 @@ pub enum RuleProduction {
 impl fmt::Debug for RuleProduction {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            RuleProduction::RuleActionSequence { rule_name, actions } => {
-                write!(f, r#"RuleActionSequence {} "#, rule_name)?;
+            RuleProduction::RuleActionSequence {
+                rule_name,
+                actions,
+                span,
+            } => {
+                write!(f, "RuleActionSequence {} at {} ", rule_name, span)?;
                 f.debug_list().entries(actions.iter()).finish()?;
                 Ok(())
             }
-            RuleProduction::UserSpecifiedName {
+            RuleProduction::Terminal {
                 rule_name,
-                user_specified_name,
-            } => write!(
-                f,
-                "UserSpecifiedName {} = {:?}",
-                rule_name, user_specified_name
-            ),
-            RuleProduction::Error => write!(f, "{:?}", self),
+                text,
+                span,
+            } => write!(f, "Terminal {} = {:?} at {}", rule_name, text, span),
+            RuleProduction::OptionalToken { token, present } => {
+                write!(f, "OptionalToken {:?} = {}", token, present)
+            }
+            RuleProduction::Error => write!(f, "Error"),
         }
     }
 }
 
 fn from_detailed(rule: DetailedRuleProduction) -> RuleProduction {
     match rule {
-        DetailedRuleProduction::RuleActionSequence { rule_name, actions } => {
+        DetailedRuleProduction::RuleActionSequence {
+            rule_name,
+            actions,
+            span,
+        } => {
             let mut high_level_actions = vec![];
             for a in actions {
                 match a {
@@ -375,28 +1369,36 @@ fn from_detailed(rule: DetailedRuleProduction) -> RuleProduction {
                             high_level_actions.push(from_detailed(p));
                         }
                     }
-                    DetailedActionProduction::Consume(_) => {}
+                    DetailedActionProduction::Consume => {}
+                    DetailedActionProduction::OptionalToken { token, present } => {
+                        high_level_actions.push(RuleProduction::OptionalToken { token, present });
+                    }
                     DetailedActionProduction::Unknown => {}
                 }
             }
             RuleProduction::RuleActionSequence {
                 rule_name,
                 actions: high_level_actions,
+                span,
             }
         }
-        DetailedRuleProduction::UserSpecifiedName {
+        DetailedRuleProduction::Terminal {
             rule_name,
-            user_specified_name,
-        } => RuleProduction::UserSpecifiedName {
+            text,
+            span,
+        } => RuleProduction::Terminal {
             rule_name,
-            user_specified_name,
+            text,
+            span,
         },
         DetailedRuleProduction::Error => RuleProduction::Error,
     }
 }
 
-pub fn run_grammar(grammar: &Grammar, tokens: &mut Vec<&str>) -> RuleProduction {
-    let details = run_rule(grammar, "<<START>>", tokens, "");
+pub fn run_grammar(grammar: &Grammar, tokens: &mut TokenStream) -> RuleProduction {
+    let lookahead = Lookahead::compute(grammar);
+    let start_rule_name = grammar.start_rule_name().unwrap();
+    let details = run_rule(grammar, &lookahead, start_rule_name, tokens, "");
     from_detailed(details)
 }
 
@@ -404,14 +1406,33 @@ pub fn parse_program(grammar: &Grammar, source: &str) {
     // let mut keys: Vec<&String> = grammar.rules.keys().collect();
     // keys.sort();
     // println!("== RULE LIST ==\n{:#?}\n", keys);
-    let mut tokens: Vec<&str> = source.split_whitespace().collect();
-    tokens.push("<<EOF>>");
+    let keywords = grammar.keywords();
+    let keywords: Vec<&str> = keywords.iter().map(|k| k.as_str()).collect();
+    let classes: Vec<(&str, &str)> = grammar
+        .terminal_classes
+        .iter()
+        .map(|c| (c.name.as_str(), c.pattern.as_str()))
+        .collect();
+    let mut tokens = match Lexer::new(&keywords, &classes).tokenize(source) {
+        Ok(tokens) => tokens,
+        Err(error) => {
+            println!(
+                "== ERROR! UNEXPECTED CHARACTER {:?} AT {} ==",
+                error.found, error.span
+            );
+            return;
+        }
+    };
     println!("== READING PROGRAM ==");
     let r = run_grammar(grammar, &mut tokens);
-    if tokens == ["<<EOF>>"] {
+    if tokens.is_at_end() {
         println!("== DONE! WELL-FORMED PROGRAM! ==");
         println!("{:#?}", r);
     } else {
-        println!("== ERROR! REMAINING PROGRAM TOKENS ==\n{:?}", tokens);
+        println!(
+            "== ERROR! UNEXPECTED TOKEN {:?} AT {} ==",
+            tokens.peek().text,
+            tokens.peek().span()
+        );
     }
 }
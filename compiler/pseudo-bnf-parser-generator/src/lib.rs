@@ -0,0 +1,1132 @@
+extern crate inflector;
+
+pub mod grammar;
+pub mod lookahead;
+
+use inflector::Inflector;
+use lookahead::{Lookahead, Terminal};
+use proc_macro2::{Ident, Literal, TokenStream};
+use quote::{format_ident, quote};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+pub use grammar::{parse_derive_list, Grammar, GrammarError};
+
+#[derive(Debug, Default, Clone)]
+pub struct Options {
+    pub module_name: Option<String>,
+    pub borrowed_names: bool,
+    // Traits to derive on AST types besides Debug, such as "Clone" or
+    // "serde::Serialize".
+    pub derives: Vec<String>,
+    pub pub_fields: bool,
+}
+
+fn to_type_name(n: &str) -> String {
+    n.to_snake_case().to_class_case()
+}
+
+fn to_field_name(n: &str) -> String {
+    n.to_snake_case()
+}
+
+fn to_optional_token_field_name(token: &str, label: &Option<String>) -> String {
+    to_field_name(label.as_deref().unwrap_or(token))
+}
+
+fn to_parser_name(n: &str) -> String {
+    format!("parse {}", n).to_snake_case()
+}
+
+fn to_visit_name(n: &str) -> String {
+    format!("visit {}", n).to_snake_case()
+}
+
+fn to_visit_mut_name(n: &str) -> String {
+    format!("visit {} mut", n).to_snake_case()
+}
+
+fn to_fold_name(n: &str) -> String {
+    format!("fold {}", n).to_snake_case()
+}
+
+fn to_class_const_name(class_name: &str) -> String {
+    format!("class {}", class_name)
+        .to_snake_case()
+        .to_uppercase()
+}
+
+const RUST_KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate",
+    "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl",
+    "in", "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref",
+    "return", "self", "Self", "static", "struct", "super", "trait", "true", "try", "type",
+    "typeof", "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+// Keywords that cannot be written as raw identifiers either.
+const NON_RAW_KEYWORDS: &[&str] = &["crate", "self", "Self", "super"];
+
+// Names the generated code already uses next to the rule types.
+const RESERVED_TYPE_NAMES: &[&str] = &[
+    "Box",
+    "Cow",
+    "Err",
+    // The generic parameters of the free visit and fold functions.
+    "F",
+    "Fold",
+    "None",
+    "Ok",
+    "Option",
+    "ParseError",
+    "Result",
+    "Some",
+    "Span",
+    "String",
+    "TokenStream",
+    "V",
+    "Vec",
+    "Visitor",
+    "VisitorMut",
+];
+
+// Keywords become raw identifiers such as `r#type`.
+fn ident(name: &str) -> Ident {
+    if RUST_KEYWORDS.contains(&name) && !NON_RAW_KEYWORDS.contains(&name) {
+        Ident::new_raw(name, proc_macro2::Span::call_site())
+    } else {
+        format_ident!("{}", name)
+    }
+}
+
+// Keywords are fine as raw identifiers, but not ones like `self` or `_`.
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && name != "_"
+        && !NON_RAW_KEYWORDS.contains(&name)
+}
+
+fn collision_errors(
+    names: BTreeMap<String, Vec<(&str, usize)>>,
+    describe: impl Fn(&str) -> String,
+) -> Vec<GrammarError> {
+    let mut errors = vec![];
+    for (name, mut sources) in names {
+        if sources.len() < 2 {
+            continue;
+        }
+        sources.sort_by_key(|&(source, line)| (line, source));
+        let (first_source, first_line) = sources[0];
+        for &(source, line) in &sources[1..] {
+            let first = if line == first_line {
+                first_source.to_string()
+            } else {
+                format!("{} (line {})", first_source, first_line)
+            };
+            errors.push(GrammarError {
+                line,
+                message: format!(
+                    "{} and {} both become the Rust {}",
+                    source,
+                    first,
+                    describe(&name)
+                ),
+            });
+        }
+    }
+    errors
+}
+
+// Rule names, labels and optional tokens that differ in the grammar can still
+// map to the same Rust identifier, or to one the generated code already uses.
+fn identifier_errors(grammar: &Grammar) -> Vec<GrammarError> {
+    let mut errors = vec![];
+    let mut type_names: BTreeMap<String, Vec<(&str, usize)>> = BTreeMap::new();
+    let mut function_names: BTreeMap<String, Vec<(&str, usize)>> = BTreeMap::new();
+    let mut parser_names = HashSet::new();
+    for rule in grammar.rules.values() {
+        let type_name = to_type_name(&rule.rule_name);
+        if RESERVED_TYPE_NAMES.contains(&type_name.as_str())
+            || NON_RAW_KEYWORDS.contains(&type_name.as_str())
+        {
+            errors.push(GrammarError {
+                line: rule.line,
+                message: format!(
+                    "{} becomes the Rust type {}, which the generated code already uses",
+                    rule.rule_name, type_name
+                ),
+            });
+        } else if !is_identifier(&type_name) {
+            errors.push(GrammarError {
+                line: rule.line,
+                message: format!("{} cannot be used as a Rust type name", rule.rule_name),
+            });
+            continue;
+        }
+        type_names
+            .entry(type_name)
+            .or_default()
+            .push((&rule.rule_name, rule.line));
+        parser_names.insert(to_parser_name(&rule.rule_name));
+        // A rule ending in -MUT can share a visit function with another rule.
+        for function_name in [
+            to_visit_name(&rule.rule_name),
+            to_visit_mut_name(&rule.rule_name),
+            to_fold_name(&rule.rule_name),
+        ] {
+            function_names
+                .entry(function_name)
+                .or_default()
+                .push((&rule.rule_name, rule.line));
+        }
+    }
+    let type_errors = collision_errors(type_names, |name| format!("type {}", name));
+    // Rules whose types collide have colliding functions too.
+    if type_errors.is_empty() {
+        errors.extend(collision_errors(function_names, |name| {
+            format!("function {}", name)
+        }));
+    }
+    errors.extend(type_errors);
+
+    for rule in grammar.rules.values() {
+        let actions = match &rule.rule_type {
+            grammar::RuleType::Actions(actions) => actions,
+            _ => continue,
+        };
+        let mut field_names: BTreeMap<String, Vec<(&str, usize)>> = BTreeMap::new();
+        let mut sources = vec![];
+        for action in actions {
+            match action {
+                grammar::RuleAction::OptionalToken { token, label: None } => {
+                    sources.push((format!("{:?}?", token), to_field_name(token)))
+                }
+                _ => {
+                    if let Some(field_label) = action.field_label() {
+                        sources.push((field_label.clone(), to_field_name(&field_label)));
+                    }
+                }
+            }
+        }
+        for (source, field_name) in &sources {
+            let clashes = !is_identifier(field_name)
+                || ["first", "input", "parse", "span"].contains(&field_name.as_str())
+                || parser_names.contains(field_name);
+            if clashes {
+                // Unlabeled optional tokens are the only sources written with quotes.
+                let hint = if source.starts_with('"') {
+                    "; give it a label, as in label:\"token\"?"
+                } else {
+                    "; give it a different label"
+                };
+                errors.push(GrammarError {
+                    line: rule.line,
+                    message: format!(
+                        "{} in {} cannot be used as a Rust field name{}",
+                        source, rule.rule_name, hint
+                    ),
+                });
+            }
+            field_names
+                .entry(field_name.clone())
+                .or_default()
+                .push((source, rule.line));
+        }
+        errors.extend(collision_errors(field_names, |name| {
+            format!("field {} of {}", name, rule.rule_name)
+        }));
+    }
+    errors
+}
+
+fn rust_prelude(options: &Options) -> TokenStream {
+    let mut prelude = quote! {
+        use pseudo_bnf_runtime as runtime;
+        pub use pseudo_bnf_runtime::{ParseError, Span, TokenStream};
+    };
+    if options.borrowed_names {
+        prelude.extend(quote! {
+            use std::borrow::Cow;
+        });
+    }
+    prelude
+}
+
+fn grammar_to_rust_lexer(grammar: &grammar::Grammar) -> TokenStream {
+    let keywords = grammar.keywords();
+    let class_names = grammar.terminal_classes.iter().map(|c| &c.name);
+    let class_patterns = grammar.terminal_classes.iter().map(|c| &c.pattern);
+    // Only classes that rules read get an index constant, since the others
+    // only exist to be rejected by the parser.
+    let mut used_classes: Vec<&String> = grammar
+        .rules
+        .values()
+        .filter_map(|rule| match &rule.rule_type {
+            grammar::RuleType::Terminal(class_name) => Some(class_name),
+            _ => None,
+        })
+        .collect();
+    used_classes.sort();
+    used_classes.dedup();
+    let class_consts = used_classes.into_iter().map(|class_name| {
+        let const_name = ident(&to_class_const_name(class_name));
+        let index = Literal::usize_unsuffixed(
+            grammar
+                .terminal_classes
+                .iter()
+                .position(|c| c.name == *class_name)
+                .unwrap(),
+        );
+        quote!(const #const_name: usize = #index;)
+    });
+    quote! {
+        pub const KEYWORDS: &[&str] = &[#(#keywords),*];
+
+        const TERMINAL_CLASSES: &[(&str, &str)] = &[#((#class_names, #class_patterns)),*];
+
+        #(#class_consts)*
+
+        pub fn tokenize(source: &str) -> Result<TokenStream<'_>, ParseError> {
+            static LEXER: std::sync::OnceLock<runtime::Lexer> = std::sync::OnceLock::new();
+            LEXER
+                .get_or_init(|| runtime::Lexer::new(KEYWORDS, TERMINAL_CLASSES))
+                .tokenize(source)
+        }
+    }
+}
+
+// Text terminals are the only values that can borrow from the source.
+fn is_text_terminal(grammar: &grammar::Grammar, class_name: &str) -> bool {
+    grammar
+        .terminal_class(class_name)
+        .is_some_and(|c| c.kind == grammar::TerminalKind::Text)
+}
+
+// With borrowed names, the rules whose types contain a name (directly or
+// through sub-rules) borrow from the source and get a lifetime parameter.
+fn borrowing_rules(grammar: &grammar::Grammar, options: &Options) -> HashSet<String> {
+    let mut borrowing = HashSet::new();
+    if !options.borrowed_names {
+        return borrowing;
+    }
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for rule in grammar.rules.values() {
+            let borrows = match &rule.rule_type {
+                grammar::RuleType::Terminal(class_name) => is_text_terminal(grammar, class_name),
+                grammar::RuleType::RuleChoice(sub_rule_names) => {
+                    sub_rule_names.iter().any(|r| borrowing.contains(r))
+                }
+                grammar::RuleType::Actions(actions) => actions.iter().any(|action| match action {
+                    grammar::RuleAction::RuleName { rule_name, .. }
+                    | grammar::RuleAction::OptionalRuleName { rule_name, .. }
+                    | grammar::RuleAction::RepeatedRuleName { rule_name, .. } => {
+                        borrowing.contains(rule_name)
+                    }
+                    _ => false,
+                }),
+            };
+            if borrows && !borrowing.contains(&rule.rule_name) {
+                borrowing.insert(rule.rule_name.clone());
+                changed = true;
+            }
+        }
+    }
+    borrowing
+}
+
+// The references that a value holds directly: fields, options and enum
+// variants, but not the elements of a Vec.
+fn by_value_references(rule: &grammar::Rule) -> Vec<&String> {
+    match &rule.rule_type {
+        grammar::RuleType::Terminal(_) => vec![],
+        grammar::RuleType::RuleChoice(sub_rule_names) => sub_rule_names.iter().collect(),
+        grammar::RuleType::Actions(actions) => actions
+            .iter()
+            .filter_map(|action| match action {
+                grammar::RuleAction::RuleName { rule_name, .. }
+                | grammar::RuleAction::OptionalRuleName { rule_name, .. } => Some(rule_name),
+                _ => None,
+            })
+            .collect(),
+    }
+}
+
+// A by-value reference from one rule to another is boxed when the referenced
+// rule leads back to the referencing one, since that type would otherwise
+// contain itself.
+fn boxed_references(grammar: &grammar::Grammar) -> HashSet<(String, String)> {
+    let mut boxed = HashSet::new();
+    for rule in grammar.rules.values() {
+        for sub_rule_name in by_value_references(rule) {
+            let mut reachable = HashSet::new();
+            let mut pending = vec![sub_rule_name];
+            while let Some(rule_name) = pending.pop() {
+                if let Some(r) = grammar.rules.get(rule_name) {
+                    if reachable.insert(rule_name) {
+                        pending.extend(by_value_references(r));
+                    }
+                }
+            }
+            if reachable.contains(&rule.rule_name) {
+                boxed.insert((rule.rule_name.clone(), sub_rule_name.clone()));
+            }
+        }
+    }
+    boxed
+}
+
+fn rust_type(rule_name: &str, borrowing: &HashSet<String>, lifetime: TokenStream) -> TokenStream {
+    let type_name = ident(&to_type_name(rule_name));
+    if borrowing.contains(rule_name) {
+        quote!(#type_name<#lifetime>)
+    } else {
+        quote!(#type_name)
+    }
+}
+
+// Converts a value of the given rule's type into its `'static` form.
+fn into_owned(
+    grammar: &grammar::Grammar,
+    rule_name: &str,
+    borrowing: &HashSet<String>,
+    boxed: bool,
+    value: TokenStream,
+) -> TokenStream {
+    if !borrowing.contains(rule_name) {
+        return value;
+    }
+    if boxed {
+        let owned = into_owned(grammar, rule_name, borrowing, false, quote!((*#value)));
+        return quote!(Box::new(#owned));
+    }
+    match grammar.rules[rule_name].rule_type {
+        grammar::RuleType::Terminal(_) => quote!(Cow::Owned(#value.into_owned())),
+        _ => quote!(#value.into_owned()),
+    }
+}
+
+// Debug is always derived; the grammar's %derive list and the options add to it.
+fn derives(grammar: &grammar::Grammar, options: &Options) -> Vec<String> {
+    let mut derives = vec!["Debug".to_string()];
+    for derive in grammar.derives.iter().chain(&options.derives) {
+        if !derives.contains(derive) {
+            derives.push(derive.clone());
+        }
+    }
+    derives
+}
+
+// f64 values cannot derive the traits that need a total equality or order.
+fn derive_errors(grammar: &Grammar, options: &Options) -> Vec<GrammarError> {
+    let unsupported: Vec<String> = derives(grammar, options)
+        .into_iter()
+        .filter(|derive| {
+            let name = derive.rsplit("::").next().unwrap();
+            ["Eq", "Hash", "Ord"].contains(&name)
+        })
+        .collect();
+    if unsupported.is_empty() {
+        return vec![];
+    }
+    grammar
+        .rules
+        .values()
+        .filter(|rule| match &rule.rule_type {
+            grammar::RuleType::Terminal(class_name) => grammar
+                .terminal_class(class_name)
+                .is_some_and(|c| c.kind == grammar::TerminalKind::Float),
+            _ => false,
+        })
+        .map(|rule| GrammarError {
+            line: rule.line,
+            message: format!(
+                "{} reads an f64, which cannot derive {}",
+                rule.rule_name,
+                unsupported.join(", ")
+            ),
+        })
+        .collect()
+}
+
+fn derive_attribute(grammar: &grammar::Grammar, options: &Options) -> TokenStream {
+    let derives = derives(grammar, options);
+    let derives = derives
+        .iter()
+        .map(|derive| syn::parse_str::<syn::Path>(derive).expect("invalid trait to derive"));
+    quote!(#[derive(#(#derives),*)])
+}
+
+fn grammar_to_rust_types(
+    grammar: &grammar::Grammar,
+    options: &Options,
+    borrowing: &HashSet<String>,
+    boxed: &HashSet<(String, String)>,
+) -> TokenStream {
+    let derive = derive_attribute(grammar, options);
+    let visibility = if grammar.pub_fields || options.pub_fields {
+        quote!(pub)
+    } else {
+        quote!()
+    };
+    let mut types = TokenStream::new();
+    for rule in grammar.rules.values() {
+        let type_name = ident(&to_type_name(&rule.rule_name));
+        let generic_type = rust_type(&rule.rule_name, borrowing, quote!('a));
+        let is_boxed =
+            |rule_name: &str| boxed.contains(&(rule.rule_name.clone(), rule_name.to_string()));
+        let field_type = |rule_name: &str| {
+            let field_type = rust_type(rule_name, borrowing, quote!('a));
+            if is_boxed(rule_name) {
+                quote!(Box<#field_type>)
+            } else {
+                field_type
+            }
+        };
+        types.extend(match &rule.rule_type {
+            grammar::RuleType::Terminal(_) if borrowing.contains(&rule.rule_name) => quote! {
+                pub type #generic_type = Cow<'a, str>;
+            },
+            grammar::RuleType::Terminal(class_name) => {
+                let value_type = match grammar.terminal_class(class_name).map(|c| c.kind) {
+                    Some(grammar::TerminalKind::Integer) => quote!(i64),
+                    Some(grammar::TerminalKind::Float) => quote!(f64),
+                    _ => quote!(String),
+                };
+                quote! {
+                    pub type #type_name = #value_type;
+                }
+            }
+            grammar::RuleType::Actions(actions) => {
+                let mut fields = vec![];
+                let mut owned_fields = vec![];
+                for a in actions {
+                    let (field_name, field, owned) = match a {
+                        grammar::RuleAction::ConsumeToken(_) => continue,
+                        grammar::RuleAction::OptionalToken { token, label } => {
+                            let field_name = ident(&to_optional_token_field_name(token, label));
+                            (field_name.clone(), quote!(bool), quote!(self.#field_name))
+                        }
+                        grammar::RuleAction::RepeatedRuleName { rule_name, .. } => {
+                            let field_name = ident(&to_field_name(&a.field_label().unwrap()));
+                            let element_type = rust_type(rule_name, borrowing, quote!('a));
+                            let element =
+                                into_owned(grammar, rule_name, borrowing, false, quote!(e));
+                            let owned = if borrowing.contains(rule_name) {
+                                quote!(self.#field_name.into_iter().map(|e| #element).collect())
+                            } else {
+                                quote!(self.#field_name)
+                            };
+                            (field_name, quote!(Vec<#element_type>), owned)
+                        }
+                        grammar::RuleAction::RuleName { rule_name, .. } => {
+                            let field_name = ident(&to_field_name(&a.field_label().unwrap()));
+                            let value = quote!(self.#field_name);
+                            let owned =
+                                into_owned(grammar, rule_name, borrowing, is_boxed(rule_name), value);
+                            (field_name, field_type(rule_name), owned)
+                        }
+                        grammar::RuleAction::OptionalRuleName { rule_name, .. } => {
+                            let field_name = ident(&to_field_name(&a.field_label().unwrap()));
+                            let element_type = field_type(rule_name);
+                            let element = into_owned(
+                                grammar,
+                                rule_name,
+                                borrowing,
+                                is_boxed(rule_name),
+                                quote!(e),
+                            );
+                            let owned = if borrowing.contains(rule_name) {
+                                quote!(self.#field_name.map(|e| #element))
+                            } else {
+                                quote!(self.#field_name)
+                            };
+                            (field_name, quote!(Option<#element_type>), owned)
+                        }
+                        grammar::RuleAction::Unknown(_) => panic!("unknown RuleAction"),
+                    };
+                    fields.push(quote!(#visibility #field_name: #field));
+                    owned_fields.push(quote!(#field_name: #owned));
+                }
+                let mut definition = quote! {
+                    #derive
+                    pub struct #generic_type {
+                        #(#fields,)*
+                        #visibility span: Span,
+                    }
+                };
+                if borrowing.contains(&rule.rule_name) {
+                    definition.extend(quote! {
+                        impl #type_name<'_> {
+                            pub fn into_owned(self) -> #type_name<'static> {
+                                #type_name {
+                                    #(#owned_fields,)*
+                                    span: self.span,
+                                }
+                            }
+                        }
+                    });
+                }
+                definition
+            }
+            grammar::RuleType::RuleChoice(sub_rule_names) => {
+                let variants: Vec<Ident> =
+                    sub_rule_names.iter().map(|r| ident(&to_type_name(r))).collect();
+                let variant_types = sub_rule_names.iter().map(|r| field_type(r));
+                let mut definition = quote! {
+                    #derive
+                    pub enum #generic_type {
+                        #(#variants(#variant_types),)*
+                    }
+                };
+                if borrowing.contains(&rule.rule_name) {
+                    let owned_variants = sub_rule_names
+                        .iter()
+                        .map(|r| into_owned(grammar, r, borrowing, is_boxed(r), quote!(v)));
+                    definition.extend(quote! {
+                        impl #type_name<'_> {
+                            pub fn into_owned(self) -> #type_name<'static> {
+                                match self {
+                                    #(#type_name::#variants(v) => #type_name::#variants(#owned_variants),)*
+                                }
+                            }
+                        }
+                    });
+                }
+                definition
+            }
+        });
+    }
+    types
+}
+
+fn generate_lookahead_check(terminals: &BTreeSet<Terminal>) -> TokenStream {
+    if terminals.is_empty() {
+        return quote!(false);
+    }
+    let conditions = terminals.iter().map(|terminal| match terminal {
+        Terminal::Token(token) => quote!(input.peek().text == #token),
+        Terminal::Class(class_name) => {
+            let const_name = ident(&to_class_const_name(class_name));
+            quote!(input.peek().class == Some(#const_name))
+        }
+        Terminal::Eof => quote!(input.is_at_end()),
+    });
+    quote!(#(#conditions)||*)
+}
+
+fn generate_repetition(
+    lookahead: &Lookahead,
+    rule_name: &str,
+    repetition: &grammar::Repetition,
+    separator: &Option<grammar::Separator>,
+) -> TokenStream {
+    let parser_name = ident(&to_parser_name(rule_name));
+    let condition = generate_lookahead_check(lookahead.first_of_rule(rule_name));
+    let start = match repetition {
+        grammar::Repetition::ZeroOrMore => condition.clone(),
+        grammar::Repetition::OneOrMore => quote!(true),
+    };
+    let next = match separator {
+        None => quote! {
+            more = #condition;
+        },
+        Some(separator) => {
+            let token = &separator.token;
+            match separator.trailing {
+                grammar::TrailingSeparator::Forbidden => quote! {
+                    more = input.peek().text == #token;
+                    if more {
+                        input.advance();
+                    }
+                },
+                grammar::TrailingSeparator::Allowed => quote! {
+                    more = input.peek().text == #token;
+                    if more {
+                        input.advance();
+                        more = #condition;
+                    }
+                },
+                grammar::TrailingSeparator::Required => quote! {
+                    runtime::consume_token(#token, input)?;
+                    more = #condition;
+                },
+            }
+        }
+    };
+    quote! {
+        {
+            let mut elements = vec![];
+            let mut more = #start;
+            while more {
+                elements.push(#parser_name(input)?);
+                #next
+            }
+            elements
+        }
+    }
+}
+
+fn grammar_to_rust_parsers(
+    grammar: &grammar::Grammar,
+    lookahead: &Lookahead,
+    borrowing: &HashSet<String>,
+    boxed: &HashSet<(String, String)>,
+) -> TokenStream {
+    let mut parsers = TokenStream::new();
+    for rule in grammar.rules.values() {
+        let type_name = ident(&to_type_name(&rule.rule_name));
+        let parse_function_name = ident(&to_parser_name(&rule.rule_name));
+        // Parses a by-value reference, boxing it where the types are recursive.
+        let parse_value = |rule_name: &str| {
+            let parser_name = ident(&to_parser_name(rule_name));
+            if boxed.contains(&(rule.rule_name.clone(), rule_name.to_string())) {
+                quote!(Box::new(#parser_name(input)?))
+            } else {
+                quote!(#parser_name(input)?)
+            }
+        };
+        let body = match &rule.rule_type {
+            grammar::RuleType::Terminal(class_name) => {
+                let const_name = ident(&to_class_const_name(class_name));
+                let read = |function: &str| {
+                    let function = ident(function);
+                    quote!(runtime::#function(#const_name, #class_name, input))
+                };
+                match grammar.terminal_class(class_name).map(|c| c.kind) {
+                    Some(grammar::TerminalKind::Integer) => read("read_integer"),
+                    Some(grammar::TerminalKind::Float) => read("read_float"),
+                    Some(grammar::TerminalKind::StringLiteral) => read("read_string_literal"),
+                    _ if borrowing.contains(&rule.rule_name) => {
+                        let read = read("read_terminal");
+                        quote!(#read.map(Cow::Borrowed))
+                    }
+                    _ => {
+                        let read = read("read_terminal");
+                        quote!(#read.map(|text| text.to_string()))
+                    }
+                }
+            }
+            grammar::RuleType::Actions(actions) => {
+                let mut statements = vec![];
+                let mut fields = vec![];
+                for a in actions {
+                    match a {
+                        grammar::RuleAction::ConsumeToken(token) => {
+                            statements.push(quote! {
+                                runtime::consume_token(#token, input)?;
+                            });
+                        }
+                        grammar::RuleAction::OptionalToken { token, label } => {
+                            let field_name = ident(&to_optional_token_field_name(token, label));
+                            statements.push(quote! {
+                                let #field_name = input.peek().text == #token;
+                                if #field_name {
+                                    input.advance();
+                                }
+                            });
+                            fields.push(field_name);
+                        }
+                        grammar::RuleAction::RepeatedRuleName {
+                            rule_name,
+                            repetition,
+                            separator,
+                            ..
+                        } => {
+                            let field_name = ident(&to_field_name(&a.field_label().unwrap()));
+                            let elements =
+                                generate_repetition(lookahead, rule_name, repetition, separator);
+                            statements.push(quote! {
+                                let #field_name = #elements;
+                            });
+                            fields.push(field_name);
+                        }
+                        grammar::RuleAction::RuleName { rule_name, .. } => {
+                            let field_name = ident(&to_field_name(&a.field_label().unwrap()));
+                            let value = parse_value(rule_name);
+                            statements.push(quote! {
+                                let #field_name = #value;
+                            });
+                            fields.push(field_name);
+                        }
+                        grammar::RuleAction::OptionalRuleName { rule_name, .. } => {
+                            let field_name = ident(&to_field_name(&a.field_label().unwrap()));
+                            let value = parse_value(rule_name);
+                            let condition =
+                                generate_lookahead_check(lookahead.first_of_rule(rule_name));
+                            statements.push(quote! {
+                                let #field_name = if #condition {
+                                    Some(#value)
+                                } else {
+                                    None
+                                };
+                            });
+                            fields.push(field_name);
+                        }
+                        grammar::RuleAction::Unknown(_) => panic!("unknown RuleAction"),
+                    }
+                }
+                quote! {
+                    let first = *input.peek();
+                    #(#statements)*
+                    Ok(#type_name {
+                        #(#fields,)*
+                        span: input.span_from(&first),
+                    })
+                }
+            }
+            grammar::RuleType::RuleChoice(sub_rule_names) => {
+                let mut checks = vec![];
+                let mut expected = vec![];
+                for rn in sub_rule_names {
+                    let terminals = lookahead.alternative_lookahead(&rule.rule_name, rn);
+                    let condition = generate_lookahead_check(&terminals);
+                    let rn_type_name = ident(&to_type_name(rn));
+                    let value = parse_value(rn);
+                    checks.push(quote! {
+                        if #condition {
+                            return Ok(#type_name::#rn_type_name(#value));
+                        }
+                    });
+                    expected.extend(terminals.iter().map(|terminal| terminal.to_string()));
+                }
+                quote! {
+                    #(#checks)*
+                    Err(runtime::unexpected(&[#(#expected),*], input))
+                }
+            }
+        };
+        parsers.extend(if borrowing.contains(&rule.rule_name) {
+            let return_type = rust_type(&rule.rule_name, borrowing, quote!('a));
+            quote! {
+                pub fn #parse_function_name<'a>(input: &mut TokenStream<'a>) -> Result<#return_type, ParseError> {
+                    #body
+                }
+            }
+        } else {
+            quote! {
+                pub fn #parse_function_name(input: &mut TokenStream) -> Result<#type_name, ParseError> {
+                    #body
+                }
+            }
+        });
+    }
+    let start_rule_name = grammar.start_rule_name().unwrap();
+    let start_type_name = rust_type(start_rule_name, borrowing, quote!('_));
+    let start_parser_name = ident(&to_parser_name(start_rule_name));
+    parsers.extend(quote! {
+        pub fn parse(source: &str) -> Result<#start_type_name, ParseError> {
+            let mut input = tokenize(source)?;
+            let program = #start_parser_name(&mut input)?;
+            if input.is_at_end() {
+                Ok(program)
+            } else {
+                Err(runtime::unexpected(&["<<EOF>>"], &input))
+            }
+        }
+    });
+    parsers
+}
+
+// Emits Visitor, VisitorMut and Fold traits in the style of syn: each method
+// defaults to a free function of the same name that recurses into the node's
+// fields, so an override can call that function to keep walking.
+fn grammar_to_rust_visitors(
+    grammar: &grammar::Grammar,
+    borrowing: &HashSet<String>,
+    boxed: &HashSet<(String, String)>,
+) -> TokenStream {
+    let mut visit_methods = vec![];
+    let mut visit_mut_methods = vec![];
+    let mut fold_methods = vec![];
+    let mut functions = TokenStream::new();
+    for rule in grammar.rules.values() {
+        let visit_name = ident(&to_visit_name(&rule.rule_name));
+        let visit_mut_name = ident(&to_visit_mut_name(&rule.rule_name));
+        let fold_name = ident(&to_fold_name(&rule.rule_name));
+        let ast_type = rust_type(&rule.rule_name, borrowing, quote!('ast));
+        let mut_type = rust_type(&rule.rule_name, borrowing, quote!('_));
+        let fold_type = rust_type(&rule.rule_name, borrowing, quote!('a));
+        let (fold_lifetime, fold_generics) = if borrowing.contains(&rule.rule_name) {
+            (quote!(<'a>), quote!(<'a, F: Fold + ?Sized>))
+        } else {
+            (quote!(), quote!(<F: Fold + ?Sized>))
+        };
+
+        visit_methods.push(quote! {
+            fn #visit_name(&mut self, node: &'ast #ast_type) {
+                #visit_name(self, node)
+            }
+        });
+        visit_mut_methods.push(quote! {
+            fn #visit_mut_name(&mut self, node: &mut #mut_type) {
+                #visit_mut_name(self, node)
+            }
+        });
+        fold_methods.push(quote! {
+            fn #fold_name #fold_lifetime(&mut self, node: #fold_type) -> #fold_type {
+                #fold_name(self, node)
+            }
+        });
+
+        let is_boxed =
+            |rule_name: &str| boxed.contains(&(rule.rule_name.clone(), rule_name.to_string()));
+        // Folds a value that the node holds directly, unboxing and reboxing it.
+        let fold_value = |rule_name: &str, value: TokenStream| {
+            let fold_name = ident(&to_fold_name(rule_name));
+            if is_boxed(rule_name) {
+                quote!(Box::new(f.#fold_name(*#value)))
+            } else {
+                quote!(f.#fold_name(#value))
+            }
+        };
+        let (visit_body, visit_mut_body, fold_body) = match &rule.rule_type {
+            // Terminal values are leaves.
+            grammar::RuleType::Terminal(_) => (quote!(), quote!(), quote!(node)),
+            grammar::RuleType::Actions(actions) => {
+                let mut visits = vec![];
+                let mut visits_mut = vec![];
+                let mut folds = vec![];
+                for a in actions {
+                    let rule_name = match a {
+                        grammar::RuleAction::ConsumeToken(_) => continue,
+                        grammar::RuleAction::OptionalToken { token, label } => {
+                            let field_name = ident(&to_optional_token_field_name(token, label));
+                            folds.push(quote!(#field_name: node.#field_name));
+                            continue;
+                        }
+                        grammar::RuleAction::RuleName { rule_name, .. }
+                        | grammar::RuleAction::OptionalRuleName { rule_name, .. }
+                        | grammar::RuleAction::RepeatedRuleName { rule_name, .. } => rule_name,
+                        grammar::RuleAction::Unknown(_) => panic!("unknown RuleAction"),
+                    };
+                    let field_name = ident(&to_field_name(&a.field_label().unwrap()));
+                    let visit_name = ident(&to_visit_name(rule_name));
+                    let visit_mut_name = ident(&to_visit_mut_name(rule_name));
+                    match a {
+                        grammar::RuleAction::RuleName { .. } => {
+                            visits.push(quote!(v.#visit_name(&node.#field_name);));
+                            visits_mut.push(quote!(v.#visit_mut_name(&mut node.#field_name);));
+                            let value = fold_value(rule_name, quote!(node.#field_name));
+                            folds.push(quote!(#field_name: #value));
+                        }
+                        grammar::RuleAction::OptionalRuleName { .. } => {
+                            visits.push(quote! {
+                                if let Some(e) = &node.#field_name {
+                                    v.#visit_name(e);
+                                }
+                            });
+                            visits_mut.push(quote! {
+                                if let Some(e) = &mut node.#field_name {
+                                    v.#visit_mut_name(e);
+                                }
+                            });
+                            let value = fold_value(rule_name, quote!(e));
+                            folds.push(quote!(#field_name: node.#field_name.map(|e| #value)));
+                        }
+                        _ => {
+                            visits.push(quote! {
+                                for e in &node.#field_name {
+                                    v.#visit_name(e);
+                                }
+                            });
+                            visits_mut.push(quote! {
+                                for e in &mut node.#field_name {
+                                    v.#visit_mut_name(e);
+                                }
+                            });
+                            let fold_name = ident(&to_fold_name(rule_name));
+                            folds.push(quote! {
+                                #field_name: node.#field_name.into_iter().map(|e| f.#fold_name(e)).collect()
+                            });
+                        }
+                    }
+                }
+                let type_name = ident(&to_type_name(&rule.rule_name));
+                (
+                    quote!(#(#visits)*),
+                    quote!(#(#visits_mut)*),
+                    quote! {
+                        #type_name {
+                            #(#folds,)*
+                            span: node.span,
+                        }
+                    },
+                )
+            }
+            grammar::RuleType::RuleChoice(sub_rule_names) => {
+                let type_name = ident(&to_type_name(&rule.rule_name));
+                let variants: Vec<Ident> = sub_rule_names
+                    .iter()
+                    .map(|r| ident(&to_type_name(r)))
+                    .collect();
+                let visit_names = sub_rule_names.iter().map(|r| ident(&to_visit_name(r)));
+                let visit_mut_names = sub_rule_names.iter().map(|r| ident(&to_visit_mut_name(r)));
+                let folds = sub_rule_names.iter().map(|r| fold_value(r, quote!(e)));
+                (
+                    quote! {
+                        match node {
+                            #(#type_name::#variants(e) => v.#visit_names(e),)*
+                        }
+                    },
+                    quote! {
+                        match node {
+                            #(#type_name::#variants(e) => v.#visit_mut_names(e),)*
+                        }
+                    },
+                    quote! {
+                        match node {
+                            #(#type_name::#variants(e) => #type_name::#variants(#folds),)*
+                        }
+                    },
+                )
+            }
+        };
+        // Nodes without any rule fields leave the visitor or folder unused.
+        let (v, f) = if visit_body.is_empty() {
+            (quote!(_v), quote!(_f))
+        } else {
+            (quote!(v), quote!(f))
+        };
+        let node = if visit_body.is_empty() {
+            quote!(_node)
+        } else {
+            quote!(node)
+        };
+        functions.extend(quote! {
+            pub fn #visit_name<'ast, V: Visitor<'ast> + ?Sized>(#v: &mut V, #node: &'ast #ast_type) {
+                #visit_body
+            }
+
+            pub fn #visit_mut_name<V: VisitorMut + ?Sized>(#v: &mut V, #node: &mut #mut_type) {
+                #visit_mut_body
+            }
+
+            pub fn #fold_name #fold_generics(#f: &mut F, node: #fold_type) -> #fold_type {
+                #fold_body
+            }
+        });
+    }
+    let mut visitors = quote! {
+        pub trait Visitor<'ast> {
+            #(#visit_methods)*
+        }
+
+        pub trait VisitorMut {
+            #(#visit_mut_methods)*
+        }
+
+        pub trait Fold {
+            #(#fold_methods)*
+        }
+    };
+    visitors.extend(functions);
+    visitors
+}
+
+pub fn read_grammar(filename: &str) -> Result<Grammar, GrammarError> {
+    grammar::read_grammar_from_file(filename)
+}
+
+pub fn parse_grammar(source: &str) -> Result<Grammar, GrammarError> {
+    grammar::read_grammar_from_str(source)
+}
+
+pub fn warnings(grammar: &Grammar) -> Vec<GrammarError> {
+    grammar.reserved_keyword_warnings()
+}
+
+pub fn validate(grammar: &Grammar, options: &Options) -> Result<(), Vec<GrammarError>> {
+    grammar.validate()?;
+    let mut errors = identifier_errors(grammar);
+    errors.extend(derive_errors(grammar, options));
+    if let Some(module_name) = &options.module_name {
+        if !is_identifier(module_name) {
+            errors.push(GrammarError {
+                line: 0,
+                message: format!("module name {:?} is not a Rust identifier", module_name),
+            });
+        }
+    }
+    if !errors.is_empty() {
+        errors.sort_by(|a, b| (a.line, &a.message).cmp(&(b.line, &b.message)));
+        return Err(errors);
+    }
+    let lookahead = Lookahead::compute(grammar);
+    let errors = lookahead.left_recursion(grammar);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let errors = lookahead.conflicts(grammar);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(())
+}
+
+// Items are pretty-printed one at a time so that they can be separated by
+// blank lines, which prettyplease does not insert on its own. The items of an
+// inline module are laid out the same way.
+fn unparse_items(items: Vec<syn::Item>) -> String {
+    let mut rust = String::new();
+    let mut previous_was_use = false;
+    for item in items {
+        let is_use = matches!(item, syn::Item::Use(_));
+        let grouped_with_previous = is_use && previous_was_use;
+        if !rust.is_empty() && !grouped_with_previous {
+            rust.push('\n');
+        }
+        match item {
+            syn::Item::Mod(syn::ItemMod {
+                vis,
+                ident,
+                content: Some((_, items)),
+                ..
+            }) => {
+                rust.push_str(&format!("{} {{\n", quote!(#vis mod #ident)));
+                for line in unparse_items(items).lines() {
+                    if !line.is_empty() {
+                        rust.push_str("    ");
+                        rust.push_str(line);
+                    }
+                    rust.push('\n');
+                }
+                rust.push_str("}\n");
+            }
+            item => rust.push_str(&prettyplease::unparse(&syn::File {
+                shebang: None,
+                attrs: vec![],
+                items: vec![item],
+            })),
+        }
+        previous_was_use = is_use;
+    }
+    rust
+}
+
+pub fn generate_rust(grammar: &Grammar, options: &Options) -> String {
+    let lookahead = Lookahead::compute(grammar);
+    let borrowing = borrowing_rules(grammar, options);
+    let boxed = boxed_references(grammar);
+    let mut rust = rust_prelude(options);
+    rust.extend(grammar_to_rust_lexer(grammar));
+    rust.extend(grammar_to_rust_types(grammar, options, &borrowing, &boxed));
+    rust.extend(grammar_to_rust_parsers(
+        grammar, &lookahead, &borrowing, &boxed,
+    ));
+    rust.extend(grammar_to_rust_visitors(grammar, &borrowing, &boxed));
+    if let Some(module_name) = &options.module_name {
+        let module_name = ident(module_name);
+        rust = quote! {
+            pub mod #module_name {
+                #rust
+            }
+        };
+    }
+    let file: syn::File = syn::parse2(rust).expect("Generated parser is not valid Rust");
+    unparse_items(file.items)
+}
@@ -0,0 +1,588 @@
+use super::grammar::{
+    Grammar, GrammarError, Repetition, Rule, RuleAction, RuleType, TrailingSeparator,
+};
+use pseudo_bnf_runtime::Token;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Terminal {
+    Token(String),
+    Class(String),
+    Eof,
+}
+
+impl fmt::Display for Terminal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Terminal::Token(token) => write!(f, "{:?}", token),
+            Terminal::Class(class_name) => write!(f, "{}", class_name),
+            Terminal::Eof => write!(f, "<<EOF>>"),
+        }
+    }
+}
+
+fn describe(terminals: &BTreeSet<Terminal>) -> String {
+    let terminals: Vec<String> = terminals.iter().map(|t| t.to_string()).collect();
+    terminals.join(", ")
+}
+
+// The tokenizer gives every token a single class, so distinct terminals never
+// match the same token.
+fn overlap(a: &BTreeSet<Terminal>, b: &BTreeSet<Terminal>) -> BTreeSet<Terminal> {
+    a.intersection(b).cloned().collect()
+}
+
+fn extend(set: &mut BTreeSet<Terminal>, terminals: &BTreeSet<Terminal>) -> bool {
+    let count = set.len();
+    set.extend(terminals.iter().cloned());
+    set.len() != count
+}
+
+#[derive(Debug)]
+pub struct Lookahead {
+    pub nullable: HashSet<String>,
+    pub first: HashMap<String, BTreeSet<Terminal>>,
+    pub follow: HashMap<String, BTreeSet<Terminal>>,
+    pub class_indices: HashMap<String, usize>,
+}
+
+impl Lookahead {
+    pub fn compute(grammar: &Grammar) -> Lookahead {
+        let empty_sets: HashMap<String, BTreeSet<Terminal>> = grammar
+            .rules
+            .keys()
+            .map(|rule_name| (rule_name.clone(), BTreeSet::new()))
+            .collect();
+        let mut lookahead = Lookahead {
+            nullable: HashSet::new(),
+            first: empty_sets.clone(),
+            follow: empty_sets,
+            class_indices: grammar
+                .terminal_classes
+                .iter()
+                .enumerate()
+                .map(|(index, class)| (class.name.clone(), index))
+                .collect(),
+        };
+        let start_rule_name = grammar.start_rule_name().unwrap_or_default();
+        if let Some(follow) = lookahead.follow.get_mut(start_rule_name) {
+            follow.insert(Terminal::Eof);
+        }
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for rule in grammar.rules.values() {
+                let (first, nullable) = match &rule.rule_type {
+                    RuleType::Terminal(class_name) => {
+                        let mut first = BTreeSet::new();
+                        first.insert(Terminal::Class(class_name.clone()));
+                        (first, false)
+                    }
+                    RuleType::RuleChoice(sub_rule_names) => {
+                        let mut first = BTreeSet::new();
+                        for sub_rule_name in sub_rule_names {
+                            first.extend(lookahead.first_of_rule(sub_rule_name).iter().cloned());
+                        }
+                        let nullable = sub_rule_names
+                            .iter()
+                            .any(|r| lookahead.nullable.contains(r));
+                        (first, nullable)
+                    }
+                    RuleType::Actions(actions) => lookahead.first_of_actions(actions),
+                };
+                changed |= extend(lookahead.first.get_mut(&rule.rule_name).unwrap(), &first);
+                if nullable {
+                    changed |= lookahead.nullable.insert(rule.rule_name.clone());
+                }
+            }
+        }
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for rule in grammar.rules.values() {
+                let follow = lookahead.follow[&rule.rule_name].clone();
+                let mut additions = vec![];
+                match &rule.rule_type {
+                    RuleType::Terminal(_) => {}
+                    RuleType::RuleChoice(sub_rule_names) => {
+                        for sub_rule_name in sub_rule_names {
+                            additions.push((sub_rule_name, follow.clone()));
+                        }
+                    }
+                    RuleType::Actions(actions) => {
+                        for (i, action) in actions.iter().enumerate() {
+                            let after = lookahead.follow_of_position(actions, i, &follow);
+                            match action {
+                                RuleAction::RuleName {
+                                    rule_name: sub_rule_name,
+                                    ..
+                                }
+                                | RuleAction::OptionalRuleName {
+                                    rule_name: sub_rule_name,
+                                    ..
+                                } => {
+                                    additions.push((sub_rule_name, after));
+                                }
+                                RuleAction::RepeatedRuleName {
+                                    rule_name: sub_rule_name,
+                                    separator,
+                                    ..
+                                } => {
+                                    let mut after = after;
+                                    match separator {
+                                        None => {
+                                            after.extend(
+                                                lookahead
+                                                    .first_of_rule(sub_rule_name)
+                                                    .iter()
+                                                    .cloned(),
+                                            );
+                                        }
+                                        Some(separator) => {
+                                            if separator.trailing == TrailingSeparator::Required {
+                                                after.clear();
+                                            }
+                                            after.insert(Terminal::Token(separator.token.clone()));
+                                        }
+                                    }
+                                    additions.push((sub_rule_name, after));
+                                }
+                                _ => {}
+                            }
+                        }
+                    }
+                }
+                for (sub_rule_name, terminals) in additions {
+                    if let Some(sub_follow) = lookahead.follow.get_mut(sub_rule_name) {
+                        changed |= extend(sub_follow, &terminals);
+                    }
+                }
+            }
+        }
+        lookahead
+    }
+
+    pub fn first_of_rule(&self, rule_name: &str) -> &BTreeSet<Terminal> {
+        &self.first[rule_name]
+    }
+
+    fn first_of_action(&self, action: &RuleAction) -> (BTreeSet<Terminal>, bool) {
+        let mut first = BTreeSet::new();
+        match action {
+            RuleAction::ConsumeToken(token) => {
+                first.insert(Terminal::Token(token.clone()));
+                (first, false)
+            }
+            RuleAction::OptionalToken { token, .. } => {
+                first.insert(Terminal::Token(token.clone()));
+                (first, true)
+            }
+            RuleAction::RuleName { rule_name, .. } => {
+                let nullable = self.nullable.contains(rule_name);
+                (self.first_of_rule(rule_name).clone(), nullable)
+            }
+            RuleAction::OptionalRuleName { rule_name, .. } => {
+                (self.first_of_rule(rule_name).clone(), true)
+            }
+            RuleAction::RepeatedRuleName {
+                rule_name,
+                repetition,
+                ..
+            } => {
+                let nullable =
+                    *repetition == Repetition::ZeroOrMore || self.nullable.contains(rule_name);
+                (self.first_of_rule(rule_name).clone(), nullable)
+            }
+            RuleAction::Unknown(_) => (first, true),
+        }
+    }
+
+    pub fn first_of_actions(&self, actions: &[RuleAction]) -> (BTreeSet<Terminal>, bool) {
+        let mut first = BTreeSet::new();
+        for action in actions {
+            let (action_first, nullable) = self.first_of_action(action);
+            first.extend(action_first);
+            if !nullable {
+                return (first, false);
+            }
+        }
+        (first, true)
+    }
+
+    // The tokens that can come after the action at `position` in a rule
+    // whose own FOLLOW set is `follow`.
+    fn follow_of_position(
+        &self,
+        actions: &[RuleAction],
+        position: usize,
+        follow: &BTreeSet<Terminal>,
+    ) -> BTreeSet<Terminal> {
+        let (mut after, nullable) = self.first_of_actions(&actions[position + 1..]);
+        if nullable {
+            after.extend(follow.iter().cloned());
+        }
+        after
+    }
+
+    pub fn alternative_lookahead(
+        &self,
+        choice_rule_name: &str,
+        alternative: &str,
+    ) -> BTreeSet<Terminal> {
+        let mut terminals = self.first_of_rule(alternative).clone();
+        if self.nullable.contains(alternative) {
+            terminals.extend(self.follow[choice_rule_name].iter().cloned());
+        }
+        terminals
+    }
+
+    pub fn matches(&self, terminal: &Terminal, token: &Token) -> bool {
+        match terminal {
+            Terminal::Token(text) => token.class.is_none() && token.text == text,
+            Terminal::Class(class_name) => {
+                token.class.is_some() && token.class == self.class_indices.get(class_name).copied()
+            }
+            Terminal::Eof => token.is_eof(),
+        }
+    }
+
+    pub fn can_start(&self, rule_name: &str, token: &Token) -> bool {
+        self.first_of_rule(rule_name)
+            .iter()
+            .any(|t| self.matches(t, token))
+    }
+
+    // The rules that can be entered before any token has been consumed.
+    fn left_corners<'a>(&self, rule: &'a Rule) -> Vec<&'a String> {
+        match &rule.rule_type {
+            RuleType::Terminal(_) => vec![],
+            RuleType::RuleChoice(sub_rule_names) => sub_rule_names.iter().collect(),
+            RuleType::Actions(actions) => {
+                let mut corners = vec![];
+                for action in actions {
+                    match action {
+                        RuleAction::RuleName { rule_name, .. }
+                        | RuleAction::OptionalRuleName { rule_name, .. }
+                        | RuleAction::RepeatedRuleName { rule_name, .. } => corners.push(rule_name),
+                        _ => {}
+                    }
+                    if !self.first_of_action(action).1 {
+                        break;
+                    }
+                }
+                corners
+            }
+        }
+    }
+
+    pub fn left_recursion(&self, grammar: &Grammar) -> Vec<GrammarError> {
+        let mut errors = vec![];
+        let mut reported = HashSet::new();
+        let mut rules: Vec<&Rule> = grammar.rules.values().collect();
+        rules.sort_by_key(|r| (r.line, r.rule_name.len(), &r.rule_name));
+        for rule in rules {
+            let start = rule.rule_name.as_str();
+            if reported.contains(&rule.line) {
+                continue;
+            }
+            let mut previous: HashMap<&str, &str> = HashMap::new();
+            let mut queue = VecDeque::new();
+            queue.push_back(start);
+            let mut cycle = None;
+            while let Some(current) = queue.pop_front() {
+                for next in self.left_corners(&grammar.rules[current]) {
+                    if next == start {
+                        let mut path = vec![start, current];
+                        let mut node = current;
+                        while node != start {
+                            node = previous[node];
+                            path.push(node);
+                        }
+                        path.reverse();
+                        cycle = Some(path);
+                        break;
+                    }
+                    if !previous.contains_key(next.as_str()) {
+                        previous.insert(next, current);
+                        queue.push_back(next);
+                    }
+                }
+                if cycle.is_some() {
+                    break;
+                }
+            }
+            if let Some(cycle) = cycle {
+                let direct = cycle.iter().all(|r| grammar.rules[*r].line == rule.line);
+                errors.push(GrammarError {
+                    line: rule.line,
+                    message: format!(
+                        "{} left recursion: {}",
+                        if direct { "direct" } else { "indirect" },
+                        cycle.join(" -> ")
+                    ),
+                });
+                reported.extend(cycle.iter().map(|r| grammar.rules[*r].line));
+            }
+        }
+        errors
+    }
+
+    pub fn conflicts(&self, grammar: &Grammar) -> Vec<GrammarError> {
+        let mut errors = vec![];
+        for rule in grammar.rules.values() {
+            let mut conflict = |message: String| {
+                errors.push(GrammarError {
+                    line: rule.line,
+                    message: format!("LL(1) conflict in {}: {}", rule.rule_name, message),
+                })
+            };
+            match &rule.rule_type {
+                RuleType::Terminal(_) => {}
+                RuleType::RuleChoice(sub_rule_names) => {
+                    for (i, a) in sub_rule_names.iter().enumerate() {
+                        for b in &sub_rule_names[i + 1..] {
+                            let shared = overlap(
+                                &self.alternative_lookahead(&rule.rule_name, a),
+                                &self.alternative_lookahead(&rule.rule_name, b),
+                            );
+                            if !shared.is_empty() {
+                                conflict(format!(
+                                    "alternatives {} and {} can both start with {}",
+                                    a,
+                                    b,
+                                    describe(&shared)
+                                ));
+                            }
+                        }
+                    }
+                }
+                RuleType::Actions(actions) => {
+                    let follow = &self.follow[&rule.rule_name];
+                    for (i, action) in actions.iter().enumerate() {
+                        let after = self.follow_of_position(actions, i, follow);
+                        let mut check = |subject: String, terminals: &BTreeSet<Terminal>| {
+                            let shared = overlap(terminals, &after);
+                            if !shared.is_empty() {
+                                conflict(format!(
+                                    "{} and what follows it can both start with {}",
+                                    subject,
+                                    describe(&shared)
+                                ));
+                            }
+                        };
+                        match action {
+                            RuleAction::OptionalToken { token, .. } => {
+                                let (first, _) = self.first_of_action(action);
+                                check(format!("optional {:?}", token), &first);
+                            }
+                            RuleAction::OptionalRuleName {
+                                rule_name: sub_rule_name,
+                                ..
+                            } => {
+                                check(
+                                    format!("optional {}", sub_rule_name),
+                                    self.first_of_rule(sub_rule_name),
+                                );
+                                if self.nullable.contains(sub_rule_name) {
+                                    conflict(format!("optional {} can be empty", sub_rule_name));
+                                }
+                            }
+                            RuleAction::RepeatedRuleName {
+                                rule_name: sub_rule_name,
+                                repetition,
+                                separator,
+                                ..
+                            } => {
+                                let subject = format!("repeated {}", sub_rule_name);
+                                let first = self.first_of_rule(sub_rule_name);
+                                let checks_first = match separator {
+                                    None => true,
+                                    Some(separator) => {
+                                        separator.trailing != TrailingSeparator::Forbidden
+                                    }
+                                };
+                                if checks_first || *repetition == Repetition::ZeroOrMore {
+                                    check(subject.clone(), first);
+                                }
+                                if let Some(separator) = separator {
+                                    if separator.trailing != TrailingSeparator::Required {
+                                        let mut token = BTreeSet::new();
+                                        token.insert(Terminal::Token(separator.token.clone()));
+                                        check(
+                                            format!(
+                                                "separator {:?} after {}",
+                                                separator.token, sub_rule_name
+                                            ),
+                                            &token,
+                                        );
+                                    }
+                                }
+                                if self.nullable.contains(sub_rule_name) {
+                                    conflict(format!("{} can be empty", subject));
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+        }
+        errors.sort_by(|a, b| (a.line, &a.message).cmp(&(b.line, &b.message)));
+        errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grammar::read_grammar_from_str;
+
+    fn terminals(terminals: &[Terminal]) -> BTreeSet<Terminal> {
+        terminals.iter().cloned().collect()
+    }
+
+    fn token(text: &str) -> Terminal {
+        Terminal::Token(text.to_string())
+    }
+
+    fn conflicts(source: &str) -> Vec<String> {
+        let grammar = read_grammar_from_str(source).unwrap();
+        grammar.validate().unwrap();
+        let lookahead = Lookahead::compute(&grammar);
+        lookahead
+            .conflicts(&grammar)
+            .iter()
+            .map(|error| error.to_string())
+            .collect()
+    }
+
+    const NESTED_CHOICES: &str = r#"
+%start <STATEMENT>
+<STATEMENT> ::= <DECLARATION> | <CALL>
+<DECLARATION> ::= <LET> | <CONST>
+<LET> ::= "let" <NAME>
+<CONST> ::= "const" <NAME>
+<CALL> ::= <NAME> "(" ")"
+<NAME> ::= <<USER-SPECIFIED-NAME>>
+"#;
+
+    fn left_recursion(source: &str) -> Vec<String> {
+        let grammar = read_grammar_from_str(source).unwrap();
+        grammar.validate().unwrap();
+        let lookahead = Lookahead::compute(&grammar);
+        lookahead
+            .left_recursion(&grammar)
+            .iter()
+            .map(|error| error.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn reports_direct_left_recursion() {
+        let source = r#"
+%start <EXPR>
+<EXPR> ::= <EXPR> "+" <TERM> | <TERM>
+<TERM> ::= <<USER-SPECIFIED-NAME>>
+"#;
+        assert_eq!(
+            left_recursion(source),
+            ["line 3: direct left recursion: <EXPR> -> <EXPR-ALTERNATIVE-1> -> <EXPR>"]
+        );
+    }
+
+    #[test]
+    fn reports_indirect_left_recursion_through_an_optional() {
+        let source = r#"
+%start <A>
+<A> ::= <B> "a" | "x"
+<B> ::= <C>? "b"
+<C> ::= <A> "c"
+"#;
+        assert_eq!(
+            left_recursion(source),
+            ["line 3: indirect left recursion: <A> -> <A-ALTERNATIVE-1> -> <B> -> <C> -> <A>"]
+        );
+    }
+
+    #[test]
+    fn rewritten_direct_left_recursion_has_no_left_recursion() {
+        let mut grammar = read_grammar_from_str(
+            r#"
+%start <EXPR>
+<EXPR> ::= <EXPR> "+" <TERM> | <EXPR> "-" <TERM> | <TERM>
+<TERM> ::= <<USER-SPECIFIED-NAME>>
+"#,
+        )
+        .unwrap();
+        grammar.rewrite_direct_left_recursion();
+        grammar.validate().unwrap();
+        let lookahead = Lookahead::compute(&grammar);
+        assert!(lookahead.left_recursion(&grammar).is_empty());
+        assert!(lookahead.conflicts(&grammar).is_empty());
+    }
+
+    #[test]
+    fn first_of_a_choice_includes_nested_choices() {
+        let grammar = read_grammar_from_str(NESTED_CHOICES).unwrap();
+        let lookahead = Lookahead::compute(&grammar);
+        assert_eq!(
+            lookahead.first_of_rule("<STATEMENT>"),
+            &terminals(&[
+                token("const"),
+                token("let"),
+                Terminal::Class("<<USER-SPECIFIED-NAME>>".to_string()),
+            ])
+        );
+        assert_eq!(
+            lookahead.follow["<NAME>"],
+            terminals(&[token("("), Terminal::Eof])
+        );
+        assert!(lookahead.conflicts(&grammar).is_empty());
+    }
+
+    #[test]
+    fn reports_alternatives_that_share_a_first_token() {
+        let source = NESTED_CHOICES.replace(r#"<CALL> ::= <NAME>"#, r#"<CALL> ::= "let""#);
+        assert_eq!(
+            conflicts(&source),
+            [
+                r#"line 3: LL(1) conflict in <STATEMENT>: alternatives <DECLARATION> and <CALL> can both start with "let""#
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_optional_token_that_overlaps_follow() {
+        let source = r#"
+%start <BLOCK>
+<BLOCK> ::= <ITEM> "end"
+<ITEM> ::= "x" "end"?
+"#;
+        let grammar = read_grammar_from_str(source).unwrap();
+        let lookahead = Lookahead::compute(&grammar);
+        assert_eq!(lookahead.follow["<ITEM>"], terminals(&[token("end")]));
+        assert_eq!(
+            conflicts(source),
+            [
+                r#"line 4: LL(1) conflict in <ITEM>: optional "end" and what follows it can both start with "end""#
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_repetition_that_overlaps_follow() {
+        let source = r#"
+%start <PAIR>
+<PAIR> ::= <WORDS> <WORD>
+<WORDS> ::= <WORD>*
+<WORD> ::= <<USER-SPECIFIED-NAME>>
+"#;
+        assert_eq!(
+            conflicts(source),
+            ["line 4: LL(1) conflict in <WORDS>: repeated <WORD> and what follows it can both start with <<USER-SPECIFIED-NAME>>"]
+        );
+    }
+}
@@ -0,0 +1,31 @@
+use pseudo_bnf_parser::grammar::{run_grammar, RuleProduction};
+use pseudo_bnf_parser::{parse_grammar, validate, Options};
+use pseudo_bnf_runtime::Lexer;
+
+#[test]
+fn rewritten_left_recursion_parses_a_chain() {
+    let mut grammar = parse_grammar(
+        "<TERM> ::= <<USER-SPECIFIED-NAME>>
+<EXPR> ::= <EXPR> \"+\" <TERM> | <TERM>
+<<START>> ::= <EXPR>
+",
+    )
+    .unwrap();
+    grammar.validate().unwrap();
+    grammar.rewrite_direct_left_recursion();
+    validate(&grammar, &Options::default()).unwrap();
+
+    let keywords = grammar.keywords();
+    let keywords: Vec<&str> = keywords.iter().map(|k| k.as_str()).collect();
+    let classes: Vec<(&str, &str)> = grammar
+        .terminal_classes
+        .iter()
+        .map(|c| (c.name.as_str(), c.pattern.as_str()))
+        .collect();
+    let mut tokens = Lexer::new(&keywords, &classes)
+        .tokenize("A + B + C")
+        .unwrap();
+    let production = run_grammar(&grammar, &mut tokens);
+    assert!(!matches!(production, RuleProduction::Error));
+    assert!(tokens.is_at_end());
+}
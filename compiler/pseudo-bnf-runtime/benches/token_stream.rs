@@ -0,0 +1,28 @@
+use pseudo_bnf_runtime::{consume_token, read_terminal, Lexer};
+use std::time::Instant;
+
+// Tokenizes and parses "Name, Name, ..." lists of growing length. With a
+// linear parser the time per token stays roughly constant across sizes.
+fn main() {
+    let lexer = Lexer::new(&[","], &[("<<NAME>>", "[A-Z][a-zA-Z-]*")]);
+    println!("{:>10} {:>14} {:>14}", "names", "total", "per name");
+    for &count in &[1_000, 10_000, 100_000, 1_000_000] {
+        let source = "Name, ".repeat(count);
+        let start = Instant::now();
+        let mut input = lexer.tokenize(&source).unwrap();
+        let mut names = 0;
+        while !input.is_at_end() {
+            read_terminal(0, "<<NAME>>", &mut input).unwrap();
+            consume_token(",", &mut input).unwrap();
+            names += 1;
+        }
+        let elapsed = start.elapsed();
+        assert_eq!(names, count);
+        println!(
+            "{:>10} {:>14?} {:>14?}",
+            count,
+            elapsed,
+            elapsed / count as u32
+        );
+    }
+}
@@ -0,0 +1,391 @@
+use regex::Regex;
+use std::fmt;
+
+// Generated AST types can derive any of these traits, so spans implement them
+// too; enable the "serde" feature for serde derives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+// A token's class is the index of the terminal class it matched, or None for
+// keywords and the EOF token. A keyword that a class would have matched in full
+// records that class as its shadowed class.
+#[derive(Debug, Clone, Copy)]
+pub struct Token<'a> {
+    pub text: &'a str,
+    pub class: Option<usize>,
+    pub shadowed_class: Option<usize>,
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Token<'_> {
+    pub fn is_eof(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    pub fn span(&self) -> Span {
+        Span {
+            start: self.offset,
+            end: self.offset + self.text.len(),
+            line: self.line,
+            column: self.column,
+        }
+    }
+}
+
+// A cursor over the tokens of a source. The last token is always the EOF
+// token, which the cursor never moves past.
+pub struct TokenStream<'a> {
+    tokens: Vec<Token<'a>>,
+    position: usize,
+    previous_end: usize,
+}
+
+impl<'a> TokenStream<'a> {
+    pub fn peek(&self) -> &Token<'a> {
+        &self.tokens[self.position]
+    }
+
+    pub fn advance(&mut self) -> Token<'a> {
+        let token = self.tokens[self.position];
+        if self.position + 1 < self.tokens.len() {
+            self.position += 1;
+            self.previous_end = token.offset + token.text.len();
+        }
+        token
+    }
+
+    pub fn is_at_end(&self) -> bool {
+        self.peek().is_eof()
+    }
+
+    pub fn span_from(&self, first: &Token) -> Span {
+        Span {
+            start: first.offset,
+            end: self.previous_end.max(first.offset),
+            line: first.line,
+            column: first.column,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub expected: Vec<String>,
+    pub found: String,
+    pub span: Span,
+    pub reserved_keyword: bool,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.reserved_keyword {
+            write!(f, "{}: {:?} is a reserved keyword", self.span, self.found)?;
+        } else if self.found.is_empty() {
+            write!(f, "{}: unexpected end of input", self.span)?;
+        } else {
+            write!(f, "{}: unexpected token {:?}", self.span, self.found)?;
+        }
+        write!(f, ", expected {}", self.expected.join(" or "))
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+pub fn unexpected(expected: &[&str], input: &TokenStream) -> ParseError {
+    ParseError {
+        expected: expected.iter().map(|e| e.to_string()).collect(),
+        found: input.peek().text.to_string(),
+        span: input.peek().span(),
+        reserved_keyword: false,
+    }
+}
+
+pub fn consume_token(token: &str, input: &mut TokenStream) -> Result<(), ParseError> {
+    if input.peek().text == token {
+        input.advance();
+        Ok(())
+    } else {
+        Err(unexpected(&[&format!("{:?}", token)], input))
+    }
+}
+
+// Reads a token of the given terminal class and converts its text, leaving
+// the input untouched if the token has another class or fails to convert.
+fn read_class<'a, T>(
+    class: usize,
+    expected: &str,
+    input: &mut TokenStream<'a>,
+    convert: impl FnOnce(&'a str) -> Option<T>,
+) -> Result<T, ParseError> {
+    let token = *input.peek();
+    if token.class != Some(class) {
+        let mut error = unexpected(&[expected], input);
+        error.reserved_keyword = token.shadowed_class == Some(class);
+        return Err(error);
+    }
+    match convert(token.text) {
+        Some(value) => {
+            input.advance();
+            Ok(value)
+        }
+        None => Err(unexpected(&[&format!("valid {}", expected)], input)),
+    }
+}
+
+pub fn read_terminal<'a>(
+    class: usize,
+    expected: &str,
+    input: &mut TokenStream<'a>,
+) -> Result<&'a str, ParseError> {
+    read_class(class, expected, input, Some)
+}
+
+pub fn read_integer(
+    class: usize,
+    expected: &str,
+    input: &mut TokenStream,
+) -> Result<i64, ParseError> {
+    read_class(class, expected, input, |text| text.parse().ok())
+}
+
+pub fn read_float(
+    class: usize,
+    expected: &str,
+    input: &mut TokenStream,
+) -> Result<f64, ParseError> {
+    read_class(class, expected, input, |text| text.parse().ok())
+}
+
+pub fn read_string_literal(
+    class: usize,
+    expected: &str,
+    input: &mut TokenStream,
+) -> Result<String, ParseError> {
+    read_class(class, expected, input, unescape_string_literal)
+}
+
+// Strips the surrounding double quotes and resolves backslash escapes, or
+// returns None for a malformed literal.
+pub fn unescape_string_literal(text: &str) -> Option<String> {
+    let inner = text.strip_prefix('"')?.strip_suffix('"')?;
+    let mut value = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        value.push(match chars.next()? {
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            '0' => '\0',
+            '\\' => '\\',
+            '"' => '"',
+            '\'' => '\'',
+            'u' => {
+                let rest = chars.as_str().strip_prefix('{')?;
+                let close = rest.find('}')?;
+                let c = char::from_u32(u32::from_str_radix(&rest[..close], 16).ok()?)?;
+                chars = rest[close + 1..].chars();
+                c
+            }
+            _ => return None,
+        });
+    }
+    Some(value)
+}
+
+// Splits sources into the longest matching keyword or terminal class token at
+// each position. Ties go to keywords first and then to the earliest class.
+pub struct Lexer {
+    keywords: Vec<String>,
+    classes: Vec<(String, Regex)>,
+}
+
+impl Lexer {
+    // Panics if a class pattern is not a valid regex.
+    pub fn new(keywords: &[&str], classes: &[(&str, &str)]) -> Lexer {
+        Lexer {
+            keywords: keywords.iter().map(|k| k.to_string()).collect(),
+            classes: classes
+                .iter()
+                .map(|(name, pattern)| {
+                    let re = Regex::new(&format!("^(?:{})", pattern)).unwrap();
+                    (name.to_string(), re)
+                })
+                .collect(),
+        }
+    }
+
+    // Ends the token stream with an empty EOF token.
+    pub fn tokenize<'a>(&self, source: &'a str) -> Result<TokenStream<'a>, ParseError> {
+        let mut tokens = vec![];
+        let mut offset = 0;
+        let mut line = 1;
+        let mut column = 1;
+        while let Some(c) = source[offset..].chars().next() {
+            let rest = &source[offset..];
+            let length = if c.is_whitespace() {
+                c.len_utf8()
+            } else {
+                let (length, class, shadowed_class) = self.longest_match(rest);
+                let mut token = Token {
+                    text: &rest[..length],
+                    class,
+                    shadowed_class,
+                    offset,
+                    line,
+                    column,
+                };
+                if length == 0 {
+                    token.text = &rest[..c.len_utf8()];
+                    return Err(ParseError {
+                        expected: self.expected(),
+                        found: token.text.to_string(),
+                        span: token.span(),
+                        reserved_keyword: false,
+                    });
+                }
+                tokens.push(token);
+                length
+            };
+            for c in rest[..length].chars() {
+                if c == '\n' {
+                    line += 1;
+                    column = 1;
+                } else {
+                    column += 1;
+                }
+            }
+            offset += length;
+        }
+        tokens.push(Token {
+            text: "",
+            class: None,
+            shadowed_class: None,
+            offset,
+            line,
+            column,
+        });
+        Ok(TokenStream {
+            tokens,
+            position: 0,
+            previous_end: 0,
+        })
+    }
+
+    fn longest_match(&self, rest: &str) -> (usize, Option<usize>, Option<usize>) {
+        let mut length = 0;
+        let mut class = None;
+        for (index, (_, re)) in self.classes.iter().enumerate() {
+            if let Some(m) = re.find(rest) {
+                if m.end() > length {
+                    length = m.end();
+                    class = Some(index);
+                }
+            }
+        }
+        let mut shadowed_class = None;
+        for keyword in &self.keywords {
+            if !keyword.is_empty() && rest.starts_with(keyword.as_str()) && keyword.len() >= length
+            {
+                if keyword.len() == length {
+                    shadowed_class = class.or(shadowed_class);
+                } else {
+                    shadowed_class = None;
+                }
+                length = keyword.len();
+                class = None;
+            }
+        }
+        (length, class, shadowed_class)
+    }
+
+    fn expected(&self) -> Vec<String> {
+        let keywords = self.keywords.iter().map(|k| format!("{:?}", k));
+        let classes = self.classes.iter().map(|(name, _)| name.clone());
+        keywords.chain(classes).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens<'a>(lexer: &Lexer, source: &'a str) -> Vec<Token<'a>> {
+        let mut input = lexer.tokenize(source).unwrap();
+        let mut tokens = vec![];
+        while !input.is_at_end() {
+            tokens.push(input.advance());
+        }
+        tokens
+    }
+
+    fn texts<'a>(lexer: &Lexer, source: &'a str) -> Vec<&'a str> {
+        tokens(lexer, source).iter().map(|t| t.text).collect()
+    }
+
+    #[test]
+    fn splits_tokens_without_whitespace() {
+        let lexer = Lexer::new(
+            &["structure", "{", ","],
+            &[("<<USER-SPECIFIED-NAME>>", "[A-Z][a-zA-Z-]*")],
+        );
+        assert_eq!(texts(&lexer, "structure{"), ["structure", "{"]);
+        let foo = tokens(&lexer, "Foo,");
+        assert_eq!(foo[0].text, "Foo");
+        assert_eq!(foo[0].class, Some(0));
+        assert_eq!(foo[1].text, ",");
+        assert_eq!(foo[1].class, None);
+    }
+
+    #[test]
+    fn longer_class_match_beats_keyword() {
+        let lexer = Lexer::new(&["new-type"], &[("<<IDENT>>", "[a-z][a-z-]*")]);
+        let token = tokens(&lexer, "new-types")[0];
+        assert_eq!(token.text, "new-types");
+        assert_eq!(token.class, Some(0));
+        assert_eq!(token.shadowed_class, None);
+    }
+
+    #[test]
+    fn keyword_wins_tie_and_records_shadowed_class() {
+        let lexer = Lexer::new(&["new-type"], &[("<<IDENT>>", "[a-z][a-z-]*")]);
+        let token = tokens(&lexer, "new-type")[0];
+        assert_eq!(token.text, "new-type");
+        assert_eq!(token.class, None);
+        assert_eq!(token.shadowed_class, Some(0));
+    }
+
+    #[test]
+    fn reports_unexpected_character() {
+        let lexer = Lexer::new(&[","], &[("<<IDENT>>", "[a-z]+")]);
+        let error = lexer.tokenize("foo,\n  $bar").err().unwrap();
+        assert_eq!(error.found, "$");
+        assert_eq!(
+            error.span,
+            Span {
+                start: 7,
+                end: 8,
+                line: 2,
+                column: 3,
+            }
+        );
+        assert_eq!(error.expected, [r#"",""#, "<<IDENT>>"]);
+    }
+}
@@ -0,0 +1,33 @@
+extern crate pseudo_bnf_parser;
+
+use pseudo_bnf_parser::{generate_rust, read_grammar, validate, warnings, Options};
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+fn main() {
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let grammar_path = Path::new(&manifest_dir).join("../../spec/torpel-grammar.pseudo-bnf");
+    let grammar_path = grammar_path.to_str().unwrap();
+    println!("cargo:rerun-if-changed={}", grammar_path);
+
+    let grammar = read_grammar(grammar_path).unwrap_or_else(|error| {
+        eprintln!("{}: {}", grammar_path, error);
+        process::exit(1);
+    });
+    if let Err(errors) = validate(&grammar, &Options::default()) {
+        for error in errors {
+            eprintln!("{}: {}", grammar_path, error);
+        }
+        process::exit(1);
+    }
+    for warning in warnings(&grammar) {
+        println!("cargo:warning={}: {}", grammar_path, warning);
+    }
+
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let rust = generate_rust(&grammar, &Options::default());
+    fs::write(Path::new(&out_dir).join("generated_parser.rs"), rust)
+        .expect("Could not write generated parser");
+}
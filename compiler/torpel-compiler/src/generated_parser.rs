@@ -1 +0,0 @@
-../../pseudo-bnf-parser-generator/generated_parser.rs
\ No newline at end of file
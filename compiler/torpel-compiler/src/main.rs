@@ -1,25 +1,23 @@
-#[macro_use]
-extern crate lazy_static;
-extern crate regex;
-
 use std::fs;
+use std::process;
 
-pub mod generated_parser;
+#[allow(dead_code)]
+pub mod generated_parser {
+    include!(concat!(env!("OUT_DIR"), "/generated_parser.rs"));
+}
 pub mod process_parse_tree;
 
 fn main() {
     let source = fs::read_to_string("spec/test-example-structures.torpel")
         .expect("Could not open program");
 
-    let mut input: Vec<&str> = source.split_whitespace().collect();
-    input.push("<<EOF>>");
-
-    let program = generated_parser::parse_start(&mut input);
-
-    if input != ["<<EOF>>"] {
-        println!("== ERROR! REMAINING PROGRAM TOKENS ==\n{:?}", input);
-        panic!("Could not parse program");
-    }
+    let program = match generated_parser::parse(&source) {
+        Ok(program) => program,
+        Err(error) => {
+            eprintln!("Could not parse program: {}", error);
+            process::exit(1);
+        }
+    };
 
     process_parse_tree::process_parse_tree(program);
 }
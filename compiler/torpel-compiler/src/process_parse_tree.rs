@@ -1,5 +1,26 @@
-use super::generated_parser;
+use super::generated_parser::{NewType, SourceFile, TypeDefinition, Visitor};
 
-pub fn process_parse_tree(start: generated_parser::Start) {
-    println!("{:#?}", start);
+struct TypePrinter;
+
+impl<'ast> Visitor<'ast> for TypePrinter {
+    fn visit_new_type(&mut self, new_type: &'ast NewType) {
+        match &new_type.definition {
+            TypeDefinition::Structure(structure) => println!(
+                "{}: structure {} {{ {} }}",
+                new_type.span,
+                new_type.name,
+                structure.field_names.join(", ")
+            ),
+            TypeDefinition::Enumeration(enumeration) => println!(
+                "{}: enumeration {} [ {} ]",
+                new_type.span,
+                new_type.name,
+                enumeration.alternative_names.join(" | ")
+            ),
+        }
+    }
+}
+
+pub fn process_parse_tree(source_file: SourceFile) {
+    TypePrinter.visit_source_file(&source_file);
 }